@@ -0,0 +1,196 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// When to start a new file. A file is finalized once any of the limits is reached.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_blocks: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_blocks: Some(100_000),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Series of files an archive handler writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Transactions,
+    Logs,
+}
+
+impl Kind {
+    pub(crate) const ALL: [Kind; 2] = [Kind::Transactions, Kind::Logs];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Kind::Transactions => "transactions",
+            Kind::Logs => "logs",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name relative to the output directory
+    pub file: String,
+    pub kind: Kind,
+    pub first_block: BlockHeight,
+    pub last_block: BlockHeight,
+    pub events: u64,
+}
+
+/// List of finalized files and the block ranges they cover, stored as
+/// `manifest.json` in the output directory. Files that are still being written
+/// have a `.partial` suffix and are not listed.
+pub struct Manifest {
+    directory: PathBuf,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn open(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        let entries = match std::fs::read(directory.join(MANIFEST_FILE)) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "partial")
+            {
+                tracing::warn!(
+                    "Found unfinished file {}, it's not listed in the manifest",
+                    path.display()
+                );
+            }
+        }
+        Ok(Self { directory, entries })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Last block in the finalized files of `kind`
+    pub(crate) fn last_block(&self, kind: Kind) -> Option<BlockHeight> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .map(|entry| entry.last_block)
+            .max()
+    }
+
+    /// Last block in the finalized files of every kind. Unfinished files
+    /// can't be resumed, so indexing continues after this block and writes
+    /// them again.
+    pub fn checkpoint(&self) -> Option<BlockHeight> {
+        Kind::ALL
+            .into_iter()
+            .map(|kind| self.last_block(kind))
+            .min()
+            .flatten()
+    }
+
+    fn add(&mut self, entry: ManifestEntry) -> io::Result<()> {
+        self.entries.push(entry);
+        // Write to a temporary file first so that a crash never leaves a truncated manifest
+        let temporary = self.directory.join(format!("{MANIFEST_FILE}.tmp"));
+        std::fs::write(&temporary, serde_json::to_vec_pretty(&self.entries)?)?;
+        std::fs::rename(temporary, self.directory.join(MANIFEST_FILE))
+    }
+}
+
+/// File that is currently being written
+pub(crate) struct Segment<W> {
+    pub writer: W,
+    kind: Kind,
+    extension: &'static str,
+    first_block: BlockHeight,
+    last_block: BlockHeight,
+    pub events: u64,
+    pub bytes: u64,
+}
+
+pub(crate) fn partial_path(
+    manifest: &Manifest,
+    kind: Kind,
+    extension: &str,
+    first_block: BlockHeight,
+) -> PathBuf {
+    manifest.directory().join(format!(
+        "{}-{first_block:012}.{extension}.partial",
+        kind.name()
+    ))
+}
+
+impl<W> Segment<W> {
+    pub fn new(writer: W, kind: Kind, extension: &'static str, first_block: BlockHeight) -> Self {
+        Self {
+            writer,
+            kind,
+            extension,
+            first_block,
+            last_block: first_block,
+            events: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Marks a block as written to this file
+    pub fn end_block(&mut self, block_height: BlockHeight) {
+        self.last_block = block_height;
+    }
+
+    pub fn should_rotate(&self, rotation: &Rotation) -> bool {
+        rotation
+            .max_blocks
+            .is_some_and(|max_blocks| self.last_block - self.first_block + 1 >= max_blocks)
+            || rotation
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes >= max_bytes)
+    }
+
+    /// Closes the file with `finish`, gives it its final name and adds it to
+    /// the manifest
+    pub fn finalize(
+        self,
+        manifest: &mut Manifest,
+        finish: impl FnOnce(W) -> io::Result<()>,
+    ) -> io::Result<()> {
+        finish(self.writer)?;
+        let file = format!(
+            "{}-{:012}-{:012}.{}",
+            self.kind.name(),
+            self.first_block,
+            self.last_block,
+            self.extension
+        );
+        std::fs::rename(
+            partial_path(manifest, self.kind, self.extension, self.first_block),
+            manifest.directory().join(&file),
+        )?;
+        manifest.add(ManifestEntry {
+            file,
+            kind: self.kind,
+            first_block: self.first_block,
+            last_block: self.last_block,
+            events: self.events,
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use inindexer::neardata::NeardataProvider;
+use inindexer::{run_indexer, BlockRange, IndexerOptions, PreprocessTransactionsSettings};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::{AuroraEventHandler, AuroraIndexer};
+
+pub struct BackfillConfig {
+    pub start_inclusive: BlockHeight,
+    pub end_exclusive: BlockHeight,
+    /// Blocks per chunk, the unit of work a worker picks up
+    pub chunk_size: BlockHeight,
+    /// Number of chunks indexed at the same time
+    pub workers: usize,
+    /// File completed chunks are recorded in
+    pub state_path: PathBuf,
+}
+
+impl BackfillConfig {
+    pub fn new(
+        start_inclusive: BlockHeight,
+        end_exclusive: BlockHeight,
+        state_path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            start_inclusive,
+            end_exclusive,
+            chunk_size: 10_000,
+            workers: 4,
+            state_path: state_path.into(),
+        }
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = (BlockHeight, BlockHeight)> + '_ {
+        (self.start_inclusive..self.end_exclusive)
+            .step_by(self.chunk_size as usize)
+            .map(|start| (start, (start + self.chunk_size).min(self.end_exclusive)))
+    }
+}
+
+/// Contents of the state file
+#[derive(Debug, Serialize, Deserialize)]
+struct BackfillState {
+    start_inclusive: BlockHeight,
+    end_exclusive: BlockHeight,
+    chunk_size: BlockHeight,
+    /// First blocks of the chunks that were fully indexed
+    completed: BTreeSet<BlockHeight>,
+}
+
+struct StateFile {
+    path: PathBuf,
+    state: BackfillState,
+}
+
+impl StateFile {
+    fn open(config: &BackfillConfig) -> Result<Self, String> {
+        let state = match std::fs::read(&config.state_path) {
+            Ok(contents) => {
+                let state: BackfillState = serde_json::from_slice(&contents)
+                    .map_err(|err| format!("Invalid backfill state: {err}"))?;
+                if (state.start_inclusive, state.end_exclusive, state.chunk_size)
+                    != (
+                        config.start_inclusive,
+                        config.end_exclusive,
+                        config.chunk_size,
+                    )
+                {
+                    return Err(format!(
+                        "{} is for blocks {}..{} in chunks of {}, remove it to start over",
+                        config.state_path.display(),
+                        state.start_inclusive,
+                        state.end_exclusive,
+                        state.chunk_size
+                    ));
+                }
+                state
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BackfillState {
+                start_inclusive: config.start_inclusive,
+                end_exclusive: config.end_exclusive,
+                chunk_size: config.chunk_size,
+                completed: BTreeSet::new(),
+            },
+            Err(err) => return Err(format!("Failed to read backfill state: {err}")),
+        };
+        Ok(Self {
+            path: config.state_path.clone(),
+            state,
+        })
+    }
+
+    fn complete(&mut self, chunk_start: BlockHeight) -> Result<(), String> {
+        self.state.completed.insert(chunk_start);
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(
+            &temporary,
+            serde_json::to_vec(&self.state).expect("Failed to serialize backfill state"),
+        )
+        .and_then(|()| std::fs::rename(&temporary, &self.path))
+        .map_err(|err| format!("Failed to save backfill state: {err}"))
+    }
+}
+
+/// Indexes a range of blocks with several indexers at once.
+///
+/// The range is split into chunks of [`BackfillConfig::chunk_size`] blocks,
+/// and each of [`BackfillConfig::workers`] workers indexes one chunk after
+/// another, each chunk with a new indexer created by `new_indexer`. Chunks
+/// finish out of order, so the sink has to accept blocks in any order and be
+/// idempotent, like the SQL sinks are. A chunk is recorded in the state file
+/// once its handler is finished, so buffered events are written by then, and
+/// recorded chunks are skipped when the backfill is started again.
+pub async fn backfill<T, F, Fut>(config: BackfillConfig, new_indexer: F) -> Result<(), String>
+where
+    T: AuroraEventHandler + Send + Sync + 'static,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = AuroraIndexer<T>> + Send,
+{
+    if config.chunk_size == 0 || config.workers == 0 {
+        return Err("The chunk size and the number of workers must be at least 1".to_owned());
+    }
+    let state = StateFile::open(&config)?;
+    let chunks = config
+        .chunks()
+        .filter(|(start, _)| !state.state.completed.contains(start))
+        .collect::<VecDeque<_>>();
+    tracing::info!(
+        "Backfilling blocks {}..{}, {} chunks left",
+        config.start_inclusive,
+        config.end_exclusive,
+        chunks.len()
+    );
+
+    let chunks = Arc::new(Mutex::new(chunks));
+    let state = Arc::new(Mutex::new(state));
+    let new_indexer = Arc::new(new_indexer);
+    let mut workers = JoinSet::new();
+    for _ in 0..config.workers {
+        let chunks = chunks.clone();
+        let state = state.clone();
+        let new_indexer = new_indexer.clone();
+        workers.spawn(async move { worker(new_indexer, chunks, state).await });
+    }
+    // Dropping the set on error aborts the other workers
+    while let Some(result) = workers.join_next().await {
+        result.map_err(|err| format!("Backfill worker panicked: {err}"))??;
+    }
+    tracing::info!("Backfill complete");
+    Ok(())
+}
+
+async fn worker<T, F, Fut>(
+    new_indexer: Arc<F>,
+    chunks: Arc<Mutex<VecDeque<(BlockHeight, BlockHeight)>>>,
+    state: Arc<Mutex<StateFile>>,
+) -> Result<(), String>
+where
+    T: AuroraEventHandler + Send + Sync + 'static,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = AuroraIndexer<T>> + Send,
+{
+    loop {
+        let next = chunks.lock().unwrap().pop_front();
+        let Some((start, end)) = next else {
+            return Ok(());
+        };
+        tracing::info!("Indexing chunk {start}..{end}");
+        let mut indexer = new_indexer().await;
+        run_indexer(
+            &mut indexer,
+            NeardataProvider::mainnet(),
+            IndexerOptions {
+                preprocess_transactions: Some(PreprocessTransactionsSettings {
+                    prefetch_blocks: 100,
+                    postfetch_blocks: 0,
+                }),
+                ..IndexerOptions::default_with_range(BlockRange::Range {
+                    start_inclusive: start,
+                    end_exclusive: Some(end),
+                })
+            },
+        )
+        .await
+        .map_err(|err| format!("Failed to index chunk {start}..{end}: {err:?}"))?;
+        // Sinks that buffer across blocks only write everything when finished
+        indexer.handler.finish().await.map_err(|err| {
+            format!("Failed to finish writing events of chunk {start}..{end}: {err}")
+        })?;
+        state.lock().unwrap().complete(start)?;
+        tracing::info!("Chunk {start}..{end} done");
+    }
+}
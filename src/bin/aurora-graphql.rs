@@ -0,0 +1,29 @@
+use aurora_indexer::graphql::{router, schema, Store};
+use aurora_indexer::telemetry::TelemetryConfig;
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let telemetry =
+        aurora_indexer::telemetry::init(TelemetryConfig::from_env().expect("Invalid $LOG_FORMAT"))
+            .expect("Failed to set up logging");
+
+    let store = Store::connect(
+        &std::env::var("POSTGRES_URL").expect("No $POSTGRES_URL environment variable set"),
+    )
+    .await
+    .expect("Failed to connect to Postgres");
+    let address = std::env::var("GRAPHQL_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8547".to_owned());
+    let listener = tokio::net::TcpListener::bind(&address)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to listen on {address}: {err}"));
+    tracing::info!("Serving GraphQL on {address}");
+    axum::serve(listener, router(schema(store)))
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .expect("GraphQL server failed");
+
+    telemetry.shutdown();
+}
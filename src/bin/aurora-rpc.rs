@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use aurora_indexer::rpc::{router, EthRpc};
+use aurora_indexer::telemetry::TelemetryConfig;
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let telemetry =
+        aurora_indexer::telemetry::init(TelemetryConfig::from_env().expect("Invalid $LOG_FORMAT"))
+            .expect("Failed to set up logging");
+
+    let rpc = EthRpc::connect(
+        &std::env::var("POSTGRES_URL").expect("No $POSTGRES_URL environment variable set"),
+    )
+    .await
+    .expect("Failed to connect to Postgres");
+    let address = std::env::var("RPC_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8545".to_owned());
+    let listener = tokio::net::TcpListener::bind(&address)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to listen on {address}: {err}"));
+    tracing::info!("Serving JSON-RPC on {address}");
+    axum::serve(listener, router(Arc::new(rpc)))
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .expect("JSON-RPC server failed");
+
+    telemetry.shutdown();
+}
@@ -0,0 +1,64 @@
+use std::ops::BitOrAssign;
+
+use aurora_engine_types::types::Address;
+
+use crate::format::hex_bytes;
+
+/// 2048-bit Ethereum logs bloom filter, as in the `logsBloom` of receipts and
+/// blocks
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bloom(pub [u8; 256]);
+
+impl Bloom {
+    /// Sets the 3 bits selected by the keccak hash of `input`
+    pub fn accrue(&mut self, input: &[u8]) {
+        let hash = aurora_engine_sdk::keccak(input);
+        for i in [0, 2, 4] {
+            let bit = ((usize::from(hash.0[i]) << 8) | usize::from(hash.0[i + 1])) & 2047;
+            self.0[255 - bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Adds a log's address and topics
+    pub fn accrue_log(&mut self, address: &Address, topics: &[[u8; 32]]) {
+        self.accrue(address.raw().as_bytes());
+        for topic in topics {
+            self.accrue(topic);
+        }
+    }
+
+    /// Whether `input` may have been added. False positives are possible.
+    pub fn contains(&self, input: &[u8]) -> bool {
+        let mut bloom = Self::default();
+        bloom.accrue(input);
+        bloom
+            .0
+            .iter()
+            .zip(&self.0)
+            .all(|(bit, set)| bit & set == *bit)
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex_bytes(&self.0)
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self([0; 256])
+    }
+}
+
+impl BitOrAssign<&Bloom> for Bloom {
+    fn bitor_assign(&mut self, other: &Bloom) {
+        for (byte, other) in self.0.iter_mut().zip(other.0) {
+            *byte |= other;
+        }
+    }
+}
+
+impl std::fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Bloom({})", self.to_hex())
+    }
+}
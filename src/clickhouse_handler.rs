@@ -0,0 +1,321 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use serde::Serialize;
+
+use crate::format::{checksum_address, hex_bytes, status_name, status_output};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+const SCHEMA: &[&str] = &[
+    r#"
+CREATE TABLE IF NOT EXISTS aurora_transactions (
+    block_height UInt64,
+    receipt_id String,
+    index UInt32,
+    block_timestamp DateTime,
+    block_timestamp_nanosec UInt64,
+    transaction_id String,
+    tx_hash String,
+    chain_id Nullable(UInt64),
+    from_address String,
+    to_address Nullable(String),
+    value UInt256,
+    input String,
+    status LowCardinality(String),
+    output Nullable(String),
+    gas_used UInt64,
+    signature Nullable(String)
+)
+ENGINE = ReplacingMergeTree
+PARTITION BY toYYYYMM(block_timestamp)
+ORDER BY (block_height, receipt_id, index)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS aurora_logs (
+    block_height UInt64,
+    receipt_id String,
+    index UInt32,
+    log_index UInt32,
+    block_timestamp DateTime,
+    block_timestamp_nanosec UInt64,
+    tx_hash String,
+    address String,
+    topics Array(String),
+    data String,
+    signature Nullable(String)
+)
+ENGINE = ReplacingMergeTree
+PARTITION BY toYYYYMM(block_timestamp)
+ORDER BY (block_height, receipt_id, index, log_index)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS aurora_checkpoint (
+    id UInt8,
+    last_flushed_height UInt64
+)
+ENGINE = ReplacingMergeTree(last_flushed_height)
+ORDER BY id
+"#,
+];
+
+pub struct ClickHouseConfig {
+    /// HTTP interface URL, e.g. `http://localhost:8123`
+    pub url: String,
+    pub database: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Rows are inserted once this many are buffered ...
+    pub batch_size: usize,
+    /// ... or when this much time passed since the last insert
+    pub batch_interval: Duration,
+}
+
+impl ClickHouseConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            database: "default".to_owned(),
+            user: None,
+            password: None,
+            batch_size: 10_000,
+            batch_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionRow {
+    block_height: BlockHeight,
+    receipt_id: String,
+    index: u32,
+    block_timestamp: u64,
+    block_timestamp_nanosec: u64,
+    transaction_id: String,
+    tx_hash: String,
+    chain_id: Option<u64>,
+    from_address: String,
+    to_address: Option<String>,
+    value: String,
+    input: String,
+    status: &'static str,
+    output: Option<String>,
+    gas_used: u64,
+    signature: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LogRow {
+    block_height: BlockHeight,
+    receipt_id: String,
+    index: u32,
+    log_index: u32,
+    block_timestamp: u64,
+    block_timestamp_nanosec: u64,
+    tx_hash: String,
+    address: String,
+    topics: Vec<String>,
+    data: String,
+    signature: Option<String>,
+}
+
+/// Buffers events and inserts them into ClickHouse in large batches.
+///
+/// Tables use `ReplacingMergeTree` ordered by `(block_height, receipt_id, index)`,
+/// so rows written again after a replay are collapsed during merges. Query with
+/// `FINAL` to get deduplicated results before the merge happens.
+///
+/// Buffered blocks aren't written yet, so the checkpoint in `aurora_checkpoint`
+/// is the last block of the last inserted batch, and indexing resumes after it.
+pub struct ClickHouseHandler {
+    client: reqwest::Client,
+    config: ClickHouseConfig,
+    transactions: Vec<TransactionRow>,
+    logs: Vec<LogRow>,
+    /// Last block in the buffer
+    buffered_height: Option<BlockHeight>,
+    /// Buffered transactions and logs of the blocks flushed so far, the rows
+    /// after them belong to the block being flushed
+    flushed_rows: (usize, usize),
+    /// `None` until the first batch, which is inserted right away so that
+    /// there's a checkpoint to resume from
+    last_insert: Option<Instant>,
+}
+
+impl ClickHouseHandler {
+    pub async fn new(config: ClickHouseConfig) -> Result<Self, reqwest::Error> {
+        let handler = Self {
+            client: reqwest::Client::new(),
+            config,
+            transactions: Vec::new(),
+            logs: Vec::new(),
+            buffered_height: None,
+            flushed_rows: (0, 0),
+            last_insert: None,
+        };
+        for statement in SCHEMA {
+            handler.query(statement, String::new()).await?;
+        }
+        Ok(handler)
+    }
+
+    /// Runs a query and returns its output
+    async fn query(&self, query: &str, body: String) -> Result<String, reqwest::Error> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .query(&[
+                ("database", &self.config.database),
+                ("query", &query.to_owned()),
+            ])
+            .body(body);
+        if let Some(user) = &self.config.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.config.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        request.send().await?.error_for_status()?.text().await
+    }
+
+    async fn insert<R: Serialize>(&self, table: &str, rows: &[R]) -> Result<(), reqwest::Error> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for row in rows {
+            body.push_str(&serde_json::to_string(row).expect("Failed to serialize row"));
+            body.push('\n');
+        }
+        self.query(&format!("INSERT INTO {table} FORMAT JSONEachRow"), body)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_buffered(&mut self) -> Result<(), reqwest::Error> {
+        let Some(block_height) = self.buffered_height else {
+            return Ok(());
+        };
+        self.insert("aurora_transactions", &self.transactions)
+            .await?;
+        self.transactions.clear();
+        self.insert("aurora_logs", &self.logs).await?;
+        self.logs.clear();
+        // After the rows, so that it never covers blocks that weren't written
+        self.query(
+            &format!(
+                "INSERT INTO aurora_checkpoint (id, last_flushed_height) VALUES (0, {block_height})"
+            ),
+            String::new(),
+        )
+        .await?;
+        self.buffered_height = None;
+        self.last_insert = Some(Instant::now());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for ClickHouseHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let receipt_id = event.receipt_id.to_string();
+        let index = self
+            .transactions
+            .iter()
+            .filter(|row| row.block_height == event.block_height && row.receipt_id == receipt_id)
+            .count() as u32;
+        self.transactions.push(TransactionRow {
+            block_height: event.block_height,
+            receipt_id,
+            index,
+            block_timestamp: (event.block_timestamp_nanosec / 1_000_000_000) as u64,
+            block_timestamp_nanosec: event.block_timestamp_nanosec as u64,
+            transaction_id: event.transaction_id.to_string(),
+            tx_hash: format!("{:#x}", details.tx_hash),
+            chain_id: event.chain_id,
+            from_address: checksum_address(&event.from),
+            to_address: event.to.as_ref().map(checksum_address),
+            value: event.value.raw().to_string(),
+            input: hex_bytes(&event.input),
+            status: status_name(&event.status),
+            output: status_output(&event.status).map(hex_bytes),
+            gas_used: details.gas_used,
+            signature: details.signature,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let receipt_id = event.receipt_id.to_string();
+        let index = self
+            .transactions
+            .iter()
+            .rev()
+            .find(|row| row.block_height == event.block_height && row.receipt_id == receipt_id)
+            .map_or(0, |row| row.index);
+        self.logs.push(LogRow {
+            block_height: event.block_height,
+            receipt_id,
+            index,
+            log_index: event.log_index as u32,
+            block_timestamp: (event.block_timestamp_nanosec / 1_000_000_000) as u64,
+            block_timestamp_nanosec: event.block_timestamp_nanosec as u64,
+            tx_hash: event.aurora_tx_hash,
+            address: checksum_address(&event.address),
+            topics: event.topics.iter().map(|topic| hex_bytes(topic)).collect(),
+            data: hex_bytes(&event.data),
+            signature: event.signature,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        // Backfilled blocks can be older than the ones before
+        self.buffered_height = Some(self.buffered_height.unwrap_or(0).max(block_height));
+        if self.transactions.len() + self.logs.len() < self.config.batch_size
+            && self
+                .last_insert
+                .is_some_and(|last_insert| last_insert.elapsed() < self.config.batch_interval)
+        {
+            self.flushed_rows = (self.transactions.len(), self.logs.len());
+            return Ok(());
+        }
+        self.insert_buffered().await?;
+        self.flushed_rows = (0, 0);
+        Ok(())
+    }
+
+    /// Drops only the rows of the block being flushed, the batch before it
+    /// stays buffered
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.truncate(self.flushed_rows.0);
+        self.logs.truncate(self.flushed_rows.1);
+        // Fewer when a failed insert already wrote some of them
+        self.flushed_rows = (self.transactions.len(), self.logs.len());
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        self.insert_buffered().await?;
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let output = self
+            .query(
+                "SELECT max(last_flushed_height) FROM aurora_checkpoint",
+                String::new(),
+            )
+            .await?;
+        // `max` of no rows is 0
+        match output.trim().parse::<BlockHeight>()? {
+            0 => Ok(None),
+            block_height => Ok(Some(block_height)),
+        }
+    }
+}
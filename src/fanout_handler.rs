@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+
+use crate::{
+    AuroraBlockEvent, AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails,
+};
+
+/// What happens when an inner handler returns an error while processing a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// The failure is propagated and stops the indexer
+    Required,
+    /// The failure is logged and the block is skipped for this handler, its
+    /// events are dropped with `discard_events`
+    BestEffort,
+    /// The block is queued and retried, together with all blocks after it, on
+    /// the next block. If more than `max_blocks` blocks are queued, the
+    /// handler is treated as required.
+    Buffered { max_blocks: usize },
+}
+
+#[derive(Clone)]
+enum Event {
+    Transaction(AuroraTransactionEvent, TransactionDetails),
+    Log(AuroraLogEvent),
+    Block(AuroraBlockEvent),
+}
+
+struct Block {
+    height: BlockHeight,
+    events: Vec<Event>,
+}
+
+struct Sink {
+    name: String,
+    handler: Box<dyn AuroraEventHandler>,
+    policy: FailurePolicy,
+    /// Blocks waiting to be retried, oldest first
+    backlog: VecDeque<Block>,
+    /// Events of the block being processed that the handler already has.
+    /// Retrying the block only passes the rest and calls `flush_events`
+    /// again, handlers keep the events they couldn't write.
+    delivered: usize,
+    last_flushed_height: Option<BlockHeight>,
+    /// Last block that was passed to the handler, whether it succeeded or not
+    last_handled_height: Option<BlockHeight>,
+}
+
+impl Sink {
+    async fn process(&mut self, block: &Block) -> Result<(), HandlerError> {
+        for event in block.events[self.delivered..].iter().cloned() {
+            match event {
+                Event::Transaction(event, details) => {
+                    self.handler.handle_transaction(event, details).await?
+                }
+                Event::Log(event) => self.handler.handle_log(event).await?,
+                Event::Block(event) => self.handler.handle_block(event).await?,
+            }
+            self.delivered += 1;
+        }
+        self.handler.flush_events(block.height).await?;
+        self.delivered = 0;
+        self.last_flushed_height = Some(block.height);
+        Ok(())
+    }
+
+    async fn process_backlog(&mut self) -> Result<(), HandlerError> {
+        while let Some(block) = self.backlog.pop_front() {
+            if let Err(err) = self.process(&block).await {
+                self.backlog.push_front(block);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_block(&mut self, block: &Block) -> Result<(), HandlerError> {
+        let result = if self.backlog.is_empty() {
+            self.process(block).await
+        } else {
+            // Keep the order, the new block goes after the ones that failed
+            self.backlog.push_back(Block {
+                height: block.height,
+                events: block.events.clone(),
+            });
+            self.process_backlog().await
+        };
+        let Err(err) = result else {
+            return Ok(());
+        };
+        match self.policy {
+            FailurePolicy::Required => {
+                return Err(format!("Handler {} failed: {err}", self.name).into());
+            }
+            FailurePolicy::BestEffort => {
+                tracing::error!(
+                    "Handler {} failed at block {}, skipping it: {err}",
+                    self.name,
+                    block.height
+                );
+                // Otherwise the handler writes them with the next block
+                self.delivered = 0;
+                if let Err(err) = self.handler.discard_events().await {
+                    tracing::error!(
+                        "Handler {} failed to discard block {}: {err}",
+                        self.name,
+                        block.height
+                    );
+                }
+            }
+            FailurePolicy::Buffered { max_blocks } => {
+                if self.backlog.is_empty() {
+                    self.backlog.push_back(Block {
+                        height: block.height,
+                        events: block.events.clone(),
+                    });
+                }
+                if self.backlog.len() > max_blocks {
+                    return Err(format!(
+                        "Handler {} has been failing for {} blocks: {err}",
+                        self.name,
+                        self.backlog.len()
+                    )
+                    .into());
+                }
+                tracing::warn!(
+                    "Handler {} failed at block {}, {} blocks queued for retry: {err}",
+                    self.name,
+                    block.height,
+                    self.backlog.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forwards events to several handlers, each with its own [`FailurePolicy`].
+///
+/// Events are collected and passed to the inner handlers when the block is
+/// flushed, one handler after another. Each event is passed to a handler only
+/// once: when a block is retried, either by the `Buffered` policy or because
+/// the whole fan-out is retried after a `Required` handler failed, the handler
+/// only gets the events it didn't get yet and `flush_events` is called again.
+/// Handlers are expected to keep the events they couldn't write until a later
+/// `flush_events` succeeds.
+#[derive(Default)]
+pub struct FanOutHandler {
+    sinks: Vec<Sink>,
+    events: Vec<Event>,
+}
+
+impl FanOutHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_handler(
+        mut self,
+        name: impl Into<String>,
+        handler: impl AuroraEventHandler + 'static,
+        policy: FailurePolicy,
+    ) -> Self {
+        self.sinks.push(Sink {
+            name: name.into(),
+            handler: Box::new(handler),
+            policy,
+            backlog: VecDeque::new(),
+            delivered: 0,
+            last_flushed_height: None,
+            last_handled_height: None,
+        });
+        self
+    }
+
+    /// Last block each handler successfully flushed, by handler name
+    pub fn progress(&self) -> Vec<(&str, Option<BlockHeight>)> {
+        self.sinks
+            .iter()
+            .map(|sink| (sink.name.as_str(), sink.last_flushed_height))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for FanOutHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        self.events.push(Event::Transaction(event, details));
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        self.events.push(Event::Log(event));
+        Ok(())
+    }
+
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        self.events.push(Event::Block(event));
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        let block = Block {
+            height: block_height,
+            events: self.events.clone(),
+        };
+        for sink in &mut self.sinks {
+            // When the block is retried after a required handler failed, the
+            // handlers before it already have it
+            if sink.last_handled_height == Some(block_height) {
+                continue;
+            }
+            sink.handle_block(&block).await?;
+            sink.last_handled_height = Some(block_height);
+        }
+        self.events.clear();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.events.clear();
+        // Queued blocks of `Buffered` handlers are older, they're kept
+        for sink in &mut self.sinks {
+            if sink.delivered > 0 && sink.backlog.is_empty() {
+                sink.handler.discard_events().await?;
+                sink.delivered = 0;
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        for sink in &mut self.sinks {
+            if let Err(err) = sink.process_backlog().await {
+                tracing::error!(
+                    "Handler {} stopped with {} blocks not written: {err}",
+                    sink.name,
+                    sink.backlog.len()
+                );
+            }
+            sink.handler.finish().await?;
+        }
+        Ok(())
+    }
+
+    /// The lowest checkpoint of all handlers, so that none of them misses a
+    /// block, or `None` if any of them doesn't have one
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let mut lowest = None;
+        for sink in &mut self.sinks {
+            let Some(height) = sink.handler.last_flushed_height().await? else {
+                return Ok(None);
+            };
+            lowest = Some(lowest.map_or(height, |lowest: BlockHeight| lowest.min(height)));
+        }
+        Ok(lowest)
+    }
+}
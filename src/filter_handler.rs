@@ -0,0 +1,246 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use serde::Deserialize;
+
+use crate::format::status_name;
+use crate::{
+    AuroraBlockEvent, AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails,
+};
+
+/// Set of conditions, all of which have to hold for a rule to match. Empty
+/// lists match anything.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRule {
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub from: Vec<[u8; 20]>,
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub to: Vec<[u8; 20]>,
+    /// Function selectors, the first 4 bytes of the input
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub selectors: Vec<[u8; 4]>,
+    /// A transaction matches if any of its logs has one of these topics, and
+    /// only the logs that have one are passed on
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub topics: Vec<[u8; 32]>,
+    /// `succeed`, `revert`, `out_of_gas`, `out_of_fund`, `out_of_offset` or `call_too_deep`
+    #[serde(default)]
+    pub status: Vec<String>,
+    #[serde(default)]
+    pub engine_account_ids: Vec<AccountId>,
+}
+
+impl FilterRule {
+    fn matches_transaction(
+        &self,
+        event: &AuroraTransactionEvent,
+        details: &TransactionDetails,
+        logs: &[AuroraLogEvent],
+    ) -> bool {
+        (self.from.is_empty() || self.from.contains(&event.from.raw().0))
+            && (self.to.is_empty() || event.to.is_some_and(|to| self.to.contains(&to.raw().0)))
+            && (self.selectors.is_empty()
+                || event
+                    .input
+                    .get(..4)
+                    .is_some_and(|selector| self.selectors.iter().any(|s| s == selector)))
+            && (self.status.is_empty()
+                || self
+                    .status
+                    .iter()
+                    .any(|status| status == status_name(&event.status)))
+            && (self.engine_account_ids.is_empty()
+                || self.engine_account_ids.contains(&details.engine_account_id))
+            && (self.topics.is_empty() || logs.iter().any(|log| self.matches_topics(log)))
+    }
+
+    fn matches_topics(&self, log: &AuroraLogEvent) -> bool {
+        self.topics.is_empty() || log.topics.iter().any(|topic| self.topics.contains(topic))
+    }
+}
+
+/// Allow and deny lists of rules. An event is passed on if it matches any
+/// `allow` rule (or there are none) and no `deny` rule.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub allow: Vec<FilterRule>,
+    #[serde(default)]
+    pub deny: Vec<FilterRule>,
+}
+
+impl EventFilter {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    fn allows_transaction(
+        &self,
+        event: &AuroraTransactionEvent,
+        details: &TransactionDetails,
+        logs: &[AuroraLogEvent],
+    ) -> bool {
+        let matches = |rule: &FilterRule| rule.matches_transaction(event, details, logs);
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+
+    fn allows_log(&self, log: &AuroraLogEvent) -> bool {
+        // Logs of denied transactions never get here, so only the topics are
+        // left to check. Rules without topics allow all logs.
+        (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches_topics(log)))
+            && !self
+                .deny
+                .iter()
+                .any(|rule| !rule.topics.is_empty() && rule.matches_topics(log))
+    }
+}
+
+struct PendingTransaction {
+    event: AuroraTransactionEvent,
+    details: TransactionDetails,
+    logs: Vec<AuroraLogEvent>,
+    /// Number of its events already passed on, the transaction and then its
+    /// logs, so that a failed release is resumed where it stopped
+    delivered: usize,
+}
+
+/// Passes on only the events allowed by an [`EventFilter`].
+///
+/// A transaction is held back until all of its logs are known, so that it can
+/// be matched by log topics. When created with [`FilterHandler::from_file`],
+/// the file is reloaded after each block if it changed.
+pub struct FilterHandler<H> {
+    pub handler: H,
+    filter: EventFilter,
+    /// Path and modification time of the loaded filter file
+    source: Option<(PathBuf, SystemTime)>,
+    pending: Option<PendingTransaction>,
+}
+
+impl<H: AuroraEventHandler> FilterHandler<H> {
+    pub fn new(handler: H, filter: EventFilter) -> Self {
+        Self {
+            handler,
+            filter,
+            source: None,
+            pending: None,
+        }
+    }
+
+    pub fn from_file(handler: H, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let modified = std::fs::metadata(&path)?.modified()?;
+        let mut handler = Self::new(handler, EventFilter::load(&path)?);
+        handler.source = Some((path, modified));
+        Ok(handler)
+    }
+
+    fn reload(&mut self) {
+        let Some((path, loaded)) = &mut self.source else {
+            return;
+        };
+        let modified = match std::fs::metadata(&*path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if modified != *loaded => modified,
+            Ok(_) => return,
+            Err(err) => {
+                tracing::warn!("Failed to check filter file {}: {err}", path.display());
+                return;
+            }
+        };
+        *loaded = modified;
+        match EventFilter::load(&*path) {
+            Ok(filter) => {
+                tracing::info!("Reloaded filter from {}", path.display());
+                self.filter = filter;
+            }
+            Err(err) => tracing::error!(
+                "Failed to reload filter from {}, keeping the old one: {err}",
+                path.display()
+            ),
+        }
+    }
+
+    async fn release_pending(&mut self) -> Result<(), HandlerError> {
+        let Some(pending) = &mut self.pending else {
+            return Ok(());
+        };
+        if pending.delivered == 0 {
+            if !self
+                .filter
+                .allows_transaction(&pending.event, &pending.details, &pending.logs)
+            {
+                self.pending = None;
+                return Ok(());
+            }
+            self.handler
+                .handle_transaction(pending.event.clone(), pending.details.clone())
+                .await?;
+            pending.delivered = 1;
+        }
+        for log in &pending.logs[pending.delivered - 1..] {
+            if self.filter.allows_log(log) {
+                self.handler.handle_log(log.clone()).await?;
+            }
+            pending.delivered += 1;
+        }
+        self.pending = None;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<H: AuroraEventHandler> AuroraEventHandler for FilterHandler<H> {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        self.release_pending().await?;
+        self.pending = Some(PendingTransaction {
+            event,
+            details,
+            logs: Vec::new(),
+            delivered: 0,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        if let Some(pending) = &mut self.pending {
+            pending.logs.push(event);
+        }
+        Ok(())
+    }
+
+    // Blocks aren't filtered
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        self.release_pending().await?;
+        self.handler.handle_block(event).await
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        self.release_pending().await?;
+        self.handler.flush_events(block_height).await?;
+        self.reload();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.pending = None;
+        self.handler.discard_events().await
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        self.handler.finish().await
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        self.handler.last_flushed_height().await
+    }
+}
@@ -0,0 +1,311 @@
+use std::str::FromStr;
+
+use aurora_engine_types::types::Address;
+use aurora_engine_types::H256;
+use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
+use inindexer::near_indexer_primitives::CryptoHash;
+use intear_events::events::aurora::transaction::{AuroraTransactionEvent, TransactionStatus};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::bloom::Bloom;
+use crate::{AuroraBlockEvent, AuroraLogEvent, BlockPosition, TransactionDetails};
+
+/// How events are encoded when pushed to a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventFormat {
+    /// Serde representation of the events, byte arrays are encoded as JSON
+    /// arrays of numbers. Kept for existing consumers.
+    #[default]
+    Legacy,
+    /// `0x` hex for bytes, EIP-55 checksummed addresses and decimal strings
+    /// for amounts, like Ethereum JSON-RPC does
+    Ethereum,
+}
+
+impl FromStr for EventFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(Self::Legacy),
+            "ethereum" => Ok(Self::Ethereum),
+            _ => Err(format!(
+                "Unknown event format {s}, expected `legacy` or `ethereum`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EthereumTransactionEvent {
+    pub block_height: BlockHeight,
+    pub block_timestamp_nanosec: u128,
+    pub transaction_id: CryptoHash,
+    pub receipt_id: CryptoHash,
+    pub chain_id: Option<u64>,
+    pub aurora_tx_hash: String,
+    pub engine_account_id: AccountId,
+    pub from: String,
+    pub to: Option<String>,
+    /// Decimal amount of wei
+    pub value: String,
+    pub input: String,
+    /// `succeed`, `revert`, `out_of_gas`, `out_of_fund`, `out_of_offset` or `call_too_deep`
+    pub status: String,
+    /// Return value of a successful transaction, or revert payload
+    pub output: Option<String>,
+    pub signature: Option<String>,
+    pub block_hash: Option<String>,
+    /// Position in the Aurora block, in execution order
+    pub transaction_index: usize,
+    pub receipt: EthereumReceipt,
+}
+
+impl EthereumTransactionEvent {
+    pub fn new(event: &AuroraTransactionEvent, details: &TransactionDetails) -> Self {
+        Self {
+            block_height: event.block_height,
+            block_timestamp_nanosec: event.block_timestamp_nanosec,
+            transaction_id: event.transaction_id,
+            receipt_id: event.receipt_id,
+            chain_id: event.chain_id,
+            aurora_tx_hash: format!("{:#x}", details.tx_hash),
+            engine_account_id: details.engine_account_id.clone(),
+            from: checksum_address(&event.from),
+            to: event.to.as_ref().map(checksum_address),
+            value: event.value.raw().to_string(),
+            input: hex_bytes(&event.input),
+            status: status_name(&event.status).to_owned(),
+            output: status_output(&event.status).map(hex_bytes),
+            signature: details.signature.clone(),
+            block_hash: details.block_hash.map(|hash| format!("{hash:#x}")),
+            transaction_index: details.transaction_index,
+            receipt: details.receipt.clone(),
+        }
+    }
+}
+
+/// Receipt of an Aurora transaction in the shape of `eth_getTransactionReceipt`
+/// results, with `0x` hex quantities. An Aurora block is made of the submits
+/// an engine account executed in a NEAR block, in execution order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthereumReceipt {
+    pub transaction_hash: String,
+    pub transaction_index: String,
+    pub block_hash: Option<String>,
+    pub block_number: String,
+    pub from: String,
+    pub to: Option<String>,
+    /// `0x1` if the transaction succeeded, `0x0` otherwise
+    pub status: String,
+    pub gas_used: String,
+    /// Gas used by this and the preceding transactions of the Aurora block
+    pub cumulative_gas_used: String,
+    pub contract_address: Option<String>,
+    pub logs: Vec<EthereumReceiptLog>,
+    pub logs_bloom: String,
+}
+
+impl EthereumReceipt {
+    pub(crate) fn new(
+        event: &AuroraTransactionEvent,
+        tx_hash: H256,
+        gas_used: u64,
+        position: BlockPosition,
+        logs: &[AuroraLogEvent],
+    ) -> Self {
+        let transaction_hash = format!("{tx_hash:#x}");
+        let block_hash = position.block_hash.map(|hash| format!("{hash:#x}"));
+        let mut logs_bloom = Bloom::default();
+        for log in logs {
+            logs_bloom.accrue_log(&log.address, &log.topics);
+        }
+        Self {
+            transaction_index: quantity(position.transaction_index as u64),
+            block_number: quantity(event.block_height),
+            from: checksum_address(&event.from),
+            to: event.to.as_ref().map(checksum_address),
+            status: quantity(matches!(event.status, TransactionStatus::Succeed(_)) as u64),
+            gas_used: quantity(gas_used),
+            cumulative_gas_used: quantity(position.cumulative_gas_used),
+            contract_address: deployed_contract(event).as_ref().map(checksum_address),
+            logs: logs
+                .iter()
+                .map(|log| EthereumReceiptLog {
+                    address: checksum_address(&log.address),
+                    topics: log.topics.iter().map(|topic| hex_bytes(topic)).collect(),
+                    data: hex_bytes(&log.data),
+                    block_hash: block_hash.clone(),
+                    block_number: quantity(event.block_height),
+                    transaction_hash: transaction_hash.clone(),
+                    transaction_index: quantity(position.transaction_index as u64),
+                    log_index: quantity((position.first_log_index + log.log_index) as u64),
+                    removed: false,
+                })
+                .collect(),
+            logs_bloom: logs_bloom.to_hex(),
+            transaction_hash,
+            block_hash,
+        }
+    }
+}
+
+/// Log in an [`EthereumReceipt`], `logIndex` counts the logs of the whole
+/// Aurora block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthereumReceiptLog {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_hash: Option<String>,
+    pub block_number: String,
+    pub transaction_hash: String,
+    pub transaction_index: String,
+    pub log_index: String,
+    pub removed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EthereumLogEvent {
+    pub block_height: BlockHeight,
+    pub block_timestamp_nanosec: u128,
+    pub transaction_id: CryptoHash,
+    pub receipt_id: CryptoHash,
+    pub aurora_tx_hash: String,
+    pub engine_account_id: AccountId,
+    pub log_index: usize,
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub signature: Option<String>,
+}
+
+impl EthereumLogEvent {
+    pub fn new(event: &AuroraLogEvent) -> Self {
+        Self {
+            block_height: event.block_height,
+            block_timestamp_nanosec: event.block_timestamp_nanosec,
+            transaction_id: event.transaction_id,
+            receipt_id: event.receipt_id,
+            aurora_tx_hash: event.aurora_tx_hash.clone(),
+            engine_account_id: event.engine_account_id.clone(),
+            log_index: event.log_index,
+            address: checksum_address(&event.address),
+            topics: event.topics.iter().map(|topic| hex_bytes(topic)).collect(),
+            data: hex_bytes(&event.data),
+            signature: event.signature.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EthereumBlockEvent {
+    pub block_height: BlockHeight,
+    pub block_timestamp_nanosec: u128,
+    pub engine_account_id: AccountId,
+    pub chain_id: Option<u64>,
+    pub block_hash: Option<String>,
+    pub transaction_count: usize,
+    pub gas_used: u64,
+    pub logs_bloom: String,
+}
+
+impl EthereumBlockEvent {
+    pub fn new(event: &AuroraBlockEvent) -> Self {
+        Self {
+            block_height: event.block_height,
+            block_timestamp_nanosec: event.block_timestamp_nanosec,
+            engine_account_id: event.engine_account_id.clone(),
+            chain_id: event.chain_id,
+            block_hash: event.block_hash.map(|hash| format!("{hash:#x}")),
+            transaction_count: event.transaction_count,
+            gas_used: event.gas_used,
+            logs_bloom: event.logs_bloom.to_hex(),
+        }
+    }
+}
+
+pub fn status_name(status: &TransactionStatus) -> &'static str {
+    match status {
+        TransactionStatus::Succeed(_) => "succeed",
+        TransactionStatus::Revert(_) => "revert",
+        TransactionStatus::OutOfGas => "out_of_gas",
+        TransactionStatus::OutOfFund => "out_of_fund",
+        TransactionStatus::OutOfOffset => "out_of_offset",
+        TransactionStatus::CallTooDeep => "call_too_deep",
+    }
+}
+
+/// Return value of a successful transaction, or revert payload
+pub fn status_output(status: &TransactionStatus) -> Option<&[u8]> {
+    match status {
+        TransactionStatus::Succeed(output) | TransactionStatus::Revert(output) => Some(output),
+        _ => None,
+    }
+}
+
+/// Message of a Solidity `Error(string)` revert
+pub fn revert_reason(output: &[u8]) -> Option<String> {
+    let data = output.strip_prefix(&[0x08, 0xc3, 0x79, 0xa0])?;
+    let length = data.get(32..64)?;
+    if length[..24].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    let length = u64::from_be_bytes(length[24..].try_into().unwrap()) as usize;
+    let message = data.get(64..64usize.checked_add(length)?)?;
+    String::from_utf8(message.to_vec()).ok()
+}
+
+/// Address of the contract created by a deployment transaction, which Aurora
+/// returns as the output of a successful transaction without `to`
+pub fn deployed_contract(event: &AuroraTransactionEvent) -> Option<Address> {
+    match (&event.to, &event.status) {
+        (None, TransactionStatus::Succeed(output)) => Address::try_from_slice(output).ok(),
+        _ => None,
+    }
+}
+
+pub fn hex_bytes(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// `0x` hex number without leading zeros, as JSON-RPC encodes quantities
+pub fn quantity(value: u64) -> String {
+    format!("{value:#x}")
+}
+
+/// Deserializes a list of `0x` hex strings of `N` bytes each
+pub(crate) fn hex_list<'de, D: Deserializer<'de>, const N: usize>(
+    deserializer: D,
+) -> Result<Vec<[u8; N]>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| {
+            hex::decode(value.trim_start_matches("0x"))
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| {
+                    serde::de::Error::custom(format!("Expected {N} hex bytes, got {value}"))
+                })
+        })
+        .collect()
+}
+
+/// EIP-55 mixed-case checksum encoding
+pub fn checksum_address(address: &Address) -> String {
+    let lowercase = hex::encode(address.raw().as_bytes());
+    let hash = aurora_engine_sdk::keccak(lowercase.as_bytes());
+    let mut checksummed = String::with_capacity(42);
+    checksummed.push_str("0x");
+    for (i, c) in lowercase.chars().enumerate() {
+        let nibble = (hash.0[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        if nibble >= 8 {
+            checksummed.push(c.to_ascii_uppercase());
+        } else {
+            checksummed.push(c);
+        }
+    }
+    checksummed
+}
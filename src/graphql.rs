@@ -0,0 +1,845 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, Enum, Error, InputObject, Object, Result, Schema,
+    SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQL, GraphQLSubscription};
+use aurora_engine_types::U256;
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use futures_util::{stream, Stream};
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use tokio::sync::broadcast;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{AsyncMessage, Client, NoTls, Row};
+
+use crate::format::hex_bytes;
+use crate::postgres_handler::BLOCKS_CHANNEL;
+
+/// Most items a page can have
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Items in a page if `first` isn't set
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+/// Deepest query the schema accepts
+const MAX_DEPTH: usize = 8;
+/// Block notifications kept for subscriptions that are busy. Subscriptions
+/// read rows after their cursor, so missing notifications only delays them.
+const NOTIFICATION_BUFFER: usize = 64;
+
+pub type AuroraSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Reads the tables of
+/// [`PostgresHandler`](crate::postgres_handler::PostgresHandler), and follows
+/// the blocks it writes through [`BLOCKS_CHANNEL`].
+pub struct Store {
+    client: Client,
+    /// Subscriptions get a copy of it. The sender is dropped when the
+    /// connection ends, which ends the subscriptions.
+    blocks: broadcast::Receiver<BlockHeight>,
+}
+
+impl Store {
+    pub async fn connect(url: &str) -> Result<Self, tokio_postgres::Error> {
+        let (client, mut connection) = tokio_postgres::connect(url, NoTls).await?;
+        let (blocks, receiver) = broadcast::channel(NOTIFICATION_BUFFER);
+        let store = Self {
+            client,
+            blocks: receiver,
+        };
+        tokio::spawn(async move {
+            loop {
+                match std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+                    Some(Ok(AsyncMessage::Notification(notification))) => {
+                        if let Ok(block_height) = notification.payload().parse() {
+                            // Nobody may be subscribed
+                            let _ = blocks.send(block_height);
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        tracing::error!("Postgres connection error, ending subscriptions: {err}");
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        });
+        store
+            .client
+            .batch_execute(&format!("LISTEN {BLOCKS_CHANNEL}"))
+            .await?;
+        Ok(store)
+    }
+
+    /// Store that doesn't listen for blocks, subscriptions end once they've
+    /// sent the blocks written before they started
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            blocks: broadcast::channel(NOTIFICATION_BUFFER).1,
+        }
+    }
+
+    /// Last block the indexer flushed
+    async fn latest_block(&self) -> Result<BlockHeight> {
+        let row = self
+            .client
+            .query_opt("SELECT last_flushed_height FROM aurora_checkpoint", &[])
+            .await
+            .map_err(internal)?;
+        Ok(row.map_or(0, |row| row.get::<_, i64>(0) as BlockHeight))
+    }
+
+    async fn transactions(
+        &self,
+        filter: &TransactionFilter,
+        order: Order,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, Transaction)>> {
+        let mut query = Query::default();
+        if let Some(from) = &filter.from {
+            let from = query.bind(addresses(from)?);
+            query.and(format!("t.from_address = ANY({from})"));
+        }
+        if let Some(to) = &filter.to {
+            let to = query.bind(addresses(to)?);
+            query.and(format!("t.to_address = ANY({to})"));
+        }
+        query.block_range(filter.from_block, filter.to_block);
+        if let Some(after) = after {
+            let [block_height, receipt_id, index] = cursor(after)?;
+            let position = [
+                query.bind(parse::<i64>(&block_height)?),
+                query.bind(receipt_id),
+                query.bind(parse::<i32>(&index)?),
+            ];
+            query.and(format!(
+                "(t.block_height, t.receipt_id, t.index) {} ({})",
+                order.after(),
+                position.join(", ")
+            ));
+        }
+        let rows = self
+            .client
+            .query(
+                &format!(
+                    "SELECT t.*, t.value::TEXT AS value_text, d.contract_address
+                    FROM aurora_transactions t
+                    LEFT JOIN aurora_deployments d USING (block_height, receipt_id, index)
+                    WHERE {}
+                    ORDER BY t.block_height {order}, t.receipt_id {order}, t.index {order}
+                    LIMIT {limit}",
+                    query.conditions(),
+                    order = order.sql(),
+                ),
+                &query.params(),
+            )
+            .await
+            .map_err(internal)?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let transaction = Transaction::from_row(row);
+                let cursor = hex::encode(format!(
+                    "{}:{}:{}",
+                    transaction.block_height, transaction.receipt_id, transaction.index
+                ));
+                (cursor, transaction)
+            })
+            .collect())
+    }
+
+    /// Logs of the transactions at `keys`, in one query
+    async fn transaction_logs(
+        &self,
+        keys: &[TransactionKey],
+    ) -> Result<HashMap<TransactionKey, Vec<Log>>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT * FROM aurora_logs
+                WHERE (block_height, receipt_id, index) IN (
+                    SELECT * FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::INTEGER[])
+                )
+                ORDER BY log_index",
+                &[
+                    &keys
+                        .iter()
+                        .map(|(block_height, _, _)| *block_height as i64)
+                        .collect::<Vec<_>>(),
+                    &keys
+                        .iter()
+                        .map(|(_, receipt_id, _)| receipt_id.as_str())
+                        .collect::<Vec<_>>(),
+                    &keys.iter().map(|(_, _, index)| *index).collect::<Vec<_>>(),
+                ],
+            )
+            .await
+            .map_err(internal)?;
+        // Transactions without logs get an empty list
+        let mut logs = keys
+            .iter()
+            .map(|key| (key.clone(), Vec::new()))
+            .collect::<HashMap<_, _>>();
+        for row in &rows {
+            let key = (
+                row.get::<_, i64>("block_height") as BlockHeight,
+                row.get("receipt_id"),
+                row.get("index"),
+            );
+            logs.entry(key).or_default().push(Log::from_row(row));
+        }
+        Ok(logs)
+    }
+
+    async fn transfers(
+        &self,
+        filter: &TransferFilter,
+        order: Order,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, Transfer)>> {
+        let mut query = Query::default();
+        let topic = query.bind(transfer_topic().to_vec());
+        // Only the logs that decode as ERC-20 or ERC-721 transfers
+        query.and(format!(
+            "l.topic0 = {topic} AND (
+                (array_length(l.topics, 1) = 3 AND length(l.data) = 32)
+                OR (array_length(l.topics, 1) = 4 AND length(l.data) = 0)
+            )"
+        ));
+        if let Some(token) = &filter.token {
+            let token = query.bind(addresses(token)?);
+            query.and(format!("l.address = ANY({token})"));
+        }
+        // Indexed addresses are left-padded to 32 bytes
+        let padded = |addresses: Vec<Vec<u8>>| {
+            addresses
+                .into_iter()
+                .map(|address| [vec![0; 12], address].concat())
+                .collect::<Vec<_>>()
+        };
+        if let Some(from) = &filter.from {
+            let from = query.bind(padded(addresses(from)?));
+            query.and(format!("l.topics[2] = ANY({from})"));
+        }
+        if let Some(to) = &filter.to {
+            let to = query.bind(padded(addresses(to)?));
+            query.and(format!("l.topics[3] = ANY({to})"));
+        }
+        query.block_range(filter.from_block, filter.to_block);
+        if let Some(after) = after {
+            let [block_height, receipt_id, index, log_index] = cursor(after)?;
+            let position = [
+                query.bind(parse::<i64>(&block_height)?),
+                query.bind(receipt_id),
+                query.bind(parse::<i32>(&index)?),
+                query.bind(parse::<i32>(&log_index)?),
+            ];
+            query.and(format!(
+                "(l.block_height, l.receipt_id, l.index, l.log_index) {} ({})",
+                order.after(),
+                position.join(", ")
+            ));
+        }
+        let rows = self
+            .client
+            .query(
+                &format!(
+                    "SELECT l.* FROM aurora_logs l
+                    WHERE {}
+                    ORDER BY l.block_height {order}, l.receipt_id {order}, l.index {order}, l.log_index {order}
+                    LIMIT {limit}",
+                    query.conditions(),
+                    order = order.sql(),
+                ),
+                &query.params(),
+            )
+            .await
+            .map_err(internal)?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let cursor = hex::encode(format!(
+                    "{}:{}:{}:{}",
+                    row.get::<_, i64>("block_height"),
+                    row.get::<_, String>("receipt_id"),
+                    row.get::<_, i32>("index"),
+                    row.get::<_, i32>("log_index"),
+                ));
+                (cursor, Transfer::from_row(row))
+            })
+            .filter_map(|(cursor, transfer)| Some((cursor, transfer?)))
+            .collect())
+    }
+}
+
+/// SQL conditions and their parameters
+#[derive(Default)]
+struct Query {
+    conditions: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl Query {
+    /// Adds a parameter and returns its placeholder
+    fn bind(&mut self, param: impl ToSql + Sync + Send + 'static) -> String {
+        self.params.push(Box::new(param));
+        format!("${}", self.params.len())
+    }
+
+    fn and(&mut self, condition: String) {
+        self.conditions.push(condition);
+    }
+
+    fn block_range(&mut self, from_block: Option<BlockHeight>, to_block: Option<BlockHeight>) {
+        if let Some(from_block) = from_block {
+            let from_block = self.bind(from_block as i64);
+            self.and(format!("block_height >= {from_block}"));
+        }
+        if let Some(to_block) = to_block {
+            let to_block = self.bind(to_block as i64);
+            self.and(format!("block_height <= {to_block}"));
+        }
+    }
+
+    fn conditions(&self) -> String {
+        if self.conditions.is_empty() {
+            "TRUE".to_owned()
+        } else {
+            self.conditions.join(" AND ")
+        }
+    }
+
+    fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// Oldest first
+    #[default]
+    Asc,
+    /// Newest first
+    Desc,
+}
+
+impl Order {
+    fn sql(self) -> &'static str {
+        match self {
+            Self::Asc => "ASC",
+            Self::Desc => "DESC",
+        }
+    }
+
+    /// Comparison selecting the rows after a cursor
+    fn after(self) -> &'static str {
+        match self {
+            Self::Asc => ">",
+            Self::Desc => "<",
+        }
+    }
+}
+
+/// All lists match any of their values, missing fields match anything
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct TransactionFilter {
+    /// Senders
+    pub from: Option<Vec<String>>,
+    /// Recipients
+    pub to: Option<Vec<String>>,
+    /// First NEAR block height, inclusive
+    pub from_block: Option<BlockHeight>,
+    /// Last NEAR block height, inclusive
+    pub to_block: Option<BlockHeight>,
+}
+
+/// All lists match any of their values, missing fields match anything
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct TransferFilter {
+    /// Token contracts
+    pub token: Option<Vec<String>>,
+    pub from: Option<Vec<String>>,
+    pub to: Option<Vec<String>>,
+    /// First NEAR block height, inclusive
+    pub from_block: Option<BlockHeight>,
+    /// Last NEAR block height, inclusive
+    pub to_block: Option<BlockHeight>,
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+#[graphql(complex)]
+pub struct Transaction {
+    pub hash: String,
+    /// NEAR block height
+    pub block_height: BlockHeight,
+    /// Unix timestamp in seconds
+    pub block_timestamp: u64,
+    pub receipt_id: String,
+    #[graphql(skip)]
+    pub index: i32,
+    pub chain_id: Option<u64>,
+    pub from: String,
+    pub to: Option<String>,
+    /// Wei, in decimal
+    pub value: String,
+    pub input: String,
+    /// `succeed`, `revert`, `out_of_gas`, `out_of_fund`, `out_of_offset` or
+    /// `call_too_deep`
+    pub status: String,
+    pub gas_used: u64,
+    /// Called function, if its selector is known
+    pub signature: Option<String>,
+    pub contract_address: Option<String>,
+}
+
+impl Transaction {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            hash: hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")),
+            block_height: row.get::<_, i64>("block_height") as BlockHeight,
+            block_timestamp: row.get::<_, i64>("block_timestamp_nanosec") as u64 / 1_000_000_000,
+            receipt_id: row.get("receipt_id"),
+            index: row.get("index"),
+            chain_id: row
+                .get::<_, Option<i64>>("chain_id")
+                .map(|chain_id| chain_id as u64),
+            from: hex_bytes(&row.get::<_, Vec<u8>>("from_address")),
+            to: row
+                .get::<_, Option<Vec<u8>>>("to_address")
+                .map(|to| hex_bytes(&to)),
+            value: row.get("value_text"),
+            input: hex_bytes(&row.get::<_, Vec<u8>>("input")),
+            status: row.get("status"),
+            gas_used: row.get::<_, i64>("gas_used") as u64,
+            signature: row.get("signature"),
+            contract_address: row
+                .get::<_, Option<Vec<u8>>>("contract_address")
+                .map(|address| hex_bytes(&address)),
+        }
+    }
+
+    async fn load_logs(&self, ctx: &Context<'_>) -> Result<Vec<Log>> {
+        let key = (self.block_height, self.receipt_id.clone(), self.index);
+        Ok(ctx
+            .data_unchecked::<DataLoader<LogLoader>>()
+            .load_one(key)
+            .await?
+            .unwrap_or_default())
+    }
+}
+
+#[ComplexObject]
+impl Transaction {
+    async fn logs(&self, ctx: &Context<'_>) -> Result<Vec<Log>> {
+        self.load_logs(ctx).await
+    }
+
+    /// ERC-20 and ERC-721 transfers in the logs
+    async fn transfers(&self, ctx: &Context<'_>) -> Result<Vec<Transfer>> {
+        Ok(self
+            .load_logs(ctx)
+            .await?
+            .into_iter()
+            .filter_map(|log| log.transfer)
+            .collect())
+    }
+}
+
+/// Position of a transaction: `(block_height, receipt_id, index)`
+type TransactionKey = (BlockHeight, String, i32);
+
+/// Reads the logs of all transactions of a page in one query, instead of one
+/// query per transaction
+struct LogLoader(Arc<Store>);
+
+impl Loader<TransactionKey> for LogLoader {
+    type Value = Vec<Log>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[TransactionKey]) -> Result<HashMap<TransactionKey, Vec<Log>>> {
+        self.0.transaction_logs(keys).await
+    }
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    /// Position in the transaction
+    pub log_index: i32,
+    /// Event, if its topic is known
+    pub signature: Option<String>,
+    pub transfer: Option<Transfer>,
+}
+
+impl Log {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            address: hex_bytes(&row.get::<_, Vec<u8>>("address")),
+            topics: row
+                .get::<_, Vec<Vec<u8>>>("topics")
+                .iter()
+                .map(|topic| hex_bytes(topic))
+                .collect(),
+            data: hex_bytes(&row.get::<_, Vec<u8>>("data")),
+            log_index: row.get("log_index"),
+            signature: row.get("signature"),
+            transfer: Transfer::from_row(row),
+        }
+    }
+}
+
+/// `Transfer(address,address,uint256)` event of an ERC-20 or ERC-721 token
+#[derive(SimpleObject, Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub token: String,
+    pub from: String,
+    pub to: String,
+    /// ERC-20 amount, in decimal
+    pub value: Option<String>,
+    /// ERC-721 token id, in decimal
+    pub token_id: Option<String>,
+    pub transaction_hash: String,
+    /// NEAR block height
+    pub block_height: BlockHeight,
+    /// Position in the transaction
+    pub log_index: i32,
+}
+
+impl Transfer {
+    /// Decodes a row of `aurora_logs`, if it's a transfer
+    fn from_row(row: &Row) -> Option<Self> {
+        let topics = row.get::<_, Vec<Vec<u8>>>("topics");
+        let data = row.get::<_, Vec<u8>>("data");
+        if topics.first()? != &transfer_topic() || topics.iter().any(|topic| topic.len() != 32) {
+            return None;
+        }
+        let (value, token_id) = match (topics.len(), data.len()) {
+            (3, 32) => (Some(U256::from_big_endian(&data).to_string()), None),
+            (4, 0) => (None, Some(U256::from_big_endian(&topics[3]).to_string())),
+            _ => return None,
+        };
+        Some(Self {
+            token: hex_bytes(&row.get::<_, Vec<u8>>("address")),
+            from: hex_bytes(&topics[1][12..]),
+            to: hex_bytes(&topics[2][12..]),
+            value,
+            token_id,
+            transaction_hash: hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")),
+            block_height: row.get::<_, i64>("block_height") as BlockHeight,
+            log_index: row.get("log_index"),
+        })
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Last block the indexer flushed
+    async fn latest_block(&self, ctx: &Context<'_>) -> Result<BlockHeight> {
+        ctx.data_unchecked::<Arc<Store>>().latest_block().await
+    }
+
+    async fn transaction(&self, ctx: &Context<'_>, hash: String) -> Result<Option<Transaction>> {
+        let hash = decode(&hash, 32)?;
+        let row = ctx
+            .data_unchecked::<Arc<Store>>()
+            .client
+            .query_opt(
+                "SELECT t.*, t.value::TEXT AS value_text, d.contract_address
+                FROM aurora_transactions t
+                LEFT JOIN aurora_deployments d USING (block_height, receipt_id, index)
+                WHERE t.tx_hash = $1
+                LIMIT 1",
+                &[&hash],
+            )
+            .await
+            .map_err(internal)?;
+        Ok(row.as_ref().map(Transaction::from_row))
+    }
+
+    /// Transactions in block order, `first` at a time after the `after` cursor
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransactionFilter>,
+        order: Option<Order>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Transaction>> {
+        let first = page_size(first)?;
+        let store = ctx.data_unchecked::<Arc<Store>>();
+        let page = store
+            .transactions(
+                &filter.unwrap_or_default(),
+                order.unwrap_or_default(),
+                after.as_deref(),
+                first + 1,
+            )
+            .await?;
+        Ok(connection(page, first, after.is_some()))
+    }
+
+    /// ERC-20 and ERC-721 transfers in block order, `first` at a time after
+    /// the `after` cursor
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransferFilter>,
+        order: Option<Order>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Transfer>> {
+        let first = page_size(first)?;
+        let store = ctx.data_unchecked::<Arc<Store>>();
+        let page = store
+            .transfers(
+                &filter.unwrap_or_default(),
+                order.unwrap_or_default(),
+                after.as_deref(),
+                first + 1,
+            )
+            .await?;
+        Ok(connection(page, first, after.is_some()))
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Transactions of the blocks the indexer writes from now on, or from
+    /// `fromBlock` if it's set. Ends after `toBlock`, if it's set.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransactionFilter>,
+    ) -> Result<impl Stream<Item = Result<Transaction>>> {
+        let store = ctx.data_unchecked::<Arc<Store>>().clone();
+        let blocks = store.blocks.resubscribe();
+        let mut filter = filter.unwrap_or_default();
+        let latest_block = store.latest_block().await?;
+        filter.from_block.get_or_insert(latest_block + 1);
+        let to_block = filter.to_block;
+        Ok(follow(blocks, latest_block, to_block, move |after| {
+            let store = store.clone();
+            let filter = filter.clone();
+            async move {
+                store
+                    .transactions(&filter, Order::Asc, after.as_deref(), MAX_PAGE_SIZE)
+                    .await
+            }
+        }))
+    }
+
+    /// Transfers of the blocks the indexer writes from now on, or from
+    /// `fromBlock` if it's set. Ends after `toBlock`, if it's set.
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransferFilter>,
+    ) -> Result<impl Stream<Item = Result<Transfer>>> {
+        let store = ctx.data_unchecked::<Arc<Store>>().clone();
+        let blocks = store.blocks.resubscribe();
+        let mut filter = filter.unwrap_or_default();
+        let latest_block = store.latest_block().await?;
+        filter.from_block.get_or_insert(latest_block + 1);
+        let to_block = filter.to_block;
+        Ok(follow(blocks, latest_block, to_block, move |after| {
+            let store = store.clone();
+            let filter = filter.clone();
+            async move {
+                store
+                    .transfers(&filter, Order::Asc, after.as_deref(), MAX_PAGE_SIZE)
+                    .await
+            }
+        }))
+    }
+}
+
+struct Follow<T, F> {
+    blocks: broadcast::Receiver<BlockHeight>,
+    fetch: F,
+    latest_block: BlockHeight,
+    to_block: Option<BlockHeight>,
+    after: Option<String>,
+    pending: VecDeque<T>,
+    /// Whether everything up to `latest_block` was sent
+    caught_up: bool,
+    failed: bool,
+}
+
+/// Pages of `fetch` after the last item sent, read again whenever a block is
+/// written, until the indexer is past `to_block`. A block may be notified
+/// while a page is read, so catching up never misses rows.
+fn follow<T, F, Fut>(
+    blocks: broadcast::Receiver<BlockHeight>,
+    latest_block: BlockHeight,
+    to_block: Option<BlockHeight>,
+    fetch: F,
+) -> impl Stream<Item = Result<T>>
+where
+    F: Fn(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<(String, T)>>>,
+{
+    let state = Follow {
+        blocks,
+        fetch,
+        latest_block,
+        to_block,
+        after: None,
+        pending: VecDeque::new(),
+        caught_up: false,
+        failed: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.pending.pop_front() {
+                return Some((Ok(item), state));
+            }
+            if state.failed {
+                return None;
+            }
+            if state.caught_up {
+                if state
+                    .to_block
+                    .is_some_and(|to_block| state.latest_block >= to_block)
+                {
+                    return None;
+                }
+                match state.blocks.recv().await {
+                    Ok(block_height) => {
+                        state.latest_block = state.latest_block.max(block_height);
+                    }
+                    // Rows are read by cursor, so skipped notifications aren't lost
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+                state.caught_up = false;
+            }
+            match (state.fetch)(state.after.clone()).await {
+                Ok(page) => {
+                    state.caught_up = page.len() < MAX_PAGE_SIZE;
+                    for (cursor, item) in page {
+                        state.after = Some(cursor);
+                        state.pending.push_back(item);
+                    }
+                }
+                Err(err) => {
+                    state.failed = true;
+                    return Some((Err(err), state));
+                }
+            }
+        }
+    })
+}
+
+pub fn schema(store: Store) -> AuroraSchema {
+    let store = Arc::new(store);
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(DataLoader::new(LogLoader(store.clone()), tokio::spawn))
+        .data(store)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// `POST /` with GraphQL queries, GraphiQL on `GET /`, and subscriptions over
+/// WebSocket on `/ws`
+pub fn router(schema: AuroraSchema) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|| async {
+                Html(
+                    GraphiQLSource::build()
+                        .endpoint("/")
+                        .subscription_endpoint("/ws")
+                        .finish(),
+                )
+            })
+            .post_service(GraphQL::new(schema.clone())),
+        )
+        .route_service("/ws", GraphQLSubscription::new(schema))
+}
+
+fn connection<T: async_graphql::OutputType>(
+    mut page: Vec<(String, T)>,
+    first: usize,
+    has_previous_page: bool,
+) -> Connection<String, T> {
+    let has_next_page = page.len() > first;
+    page.truncate(first);
+    let mut connection = Connection::new(has_previous_page, has_next_page);
+    connection.edges.extend(
+        page.into_iter()
+            .map(|(cursor, node)| Edge::new(cursor, node)),
+    );
+    connection
+}
+
+fn page_size(first: Option<i32>) -> Result<usize> {
+    match first.map_or(Ok(DEFAULT_PAGE_SIZE), usize::try_from) {
+        Ok(first @ 1..=MAX_PAGE_SIZE) => Ok(first),
+        _ => Err(Error::new(format!(
+            "first has to be between 1 and {MAX_PAGE_SIZE}"
+        ))),
+    }
+}
+
+/// Position encoded by a cursor
+fn cursor<const N: usize>(cursor: &str) -> Result<[String; N]> {
+    hex::decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|position| {
+            position
+                .split(':')
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+                .try_into()
+                .ok()
+        })
+        .ok_or_else(|| Error::new(format!("Invalid cursor {cursor}")))
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::new(format!("Invalid cursor position {value}")))
+}
+
+fn decode(value: &str, len: usize) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x"))
+        .ok()
+        .filter(|bytes| bytes.len() == len)
+        .ok_or_else(|| Error::new(format!("Expected {len} hex bytes, got {value}")))
+}
+
+fn addresses(values: &[String]) -> Result<Vec<Vec<u8>>> {
+    values.iter().map(|value| decode(value, 20)).collect()
+}
+
+/// `Transfer(address,address,uint256)` topic
+fn transfer_topic() -> [u8; 32] {
+    aurora_engine_sdk::keccak(b"Transfer(address,address,uint256)").0
+}
+
+fn internal(err: tokio_postgres::Error) -> Error {
+    tracing::error!("Query failed: {err}");
+    Error::new("Internal error")
+}
@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+
+#[derive(Default)]
+struct State {
+    /// Height and timestamp of the last flushed block
+    last_flushed: Option<(BlockHeight, SystemTime)>,
+    /// Last sink error since the last flushed block
+    sink_error: Option<String>,
+}
+
+/// Readiness of an indexer, updated as blocks are flushed.
+///
+/// The indexer is ready when its last flushed block is at most
+/// `max_block_age` old, going by the block timestamp, and no sink failed
+/// since. Clones share the same state, so one instance can be passed to
+/// [`AuroraIndexer::with_health`](crate::AuroraIndexer::with_health) and
+/// [`RetryHandler::with_health`](crate::retry_handler::RetryHandler::with_health),
+/// and served with [`router`].
+#[derive(Clone)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+    max_block_age: Duration,
+}
+
+impl Health {
+    pub fn new(max_block_age: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            max_block_age,
+        }
+    }
+
+    pub(crate) fn block_flushed(&self, block_height: BlockHeight, timestamp: SystemTime) {
+        let mut state = self.state.lock().unwrap();
+        state.last_flushed = Some((block_height, timestamp));
+        state.sink_error = None;
+    }
+
+    pub(crate) fn sink_failed(&self, err: impl ToString) {
+        self.state.lock().unwrap().sink_error = Some(err.to_string());
+    }
+
+    /// Height of the last flushed block, or why the indexer isn't ready
+    pub fn readiness(&self) -> Result<BlockHeight, String> {
+        let state = self.state.lock().unwrap();
+        if let Some(err) = &state.sink_error {
+            return Err(format!("Sink is failing: {err}"));
+        }
+        let Some((block_height, timestamp)) = state.last_flushed else {
+            return Err("No block flushed yet".to_owned());
+        };
+        let age = SystemTime::now()
+            .duration_since(timestamp)
+            .unwrap_or_default();
+        if age > self.max_block_age {
+            return Err(format!(
+                "Last flushed block {block_height} is {}s old",
+                age.as_secs()
+            ));
+        }
+        Ok(block_height)
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(120))
+    }
+}
+
+/// `GET /healthz`, which succeeds while the process is serving, and
+/// `GET /readyz`, which fails with 503 when the indexer isn't
+/// [ready](Health::readiness)
+pub fn router(health: Health) -> Router {
+    Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route(
+            "/readyz",
+            get(move || async move {
+                match health.readiness() {
+                    Ok(block_height) => (StatusCode::OK, format!("ok, at block {block_height}")),
+                    Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+                }
+            }),
+        )
+}
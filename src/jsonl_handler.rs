@@ -0,0 +1,272 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+
+use crate::archive::{partial_path, Kind, Manifest, Rotation, Segment};
+use crate::format::{EthereumLogEvent, EthereumTransactionEvent, EventFormat};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+pub struct JsonlConfig {
+    pub directory: PathBuf,
+    pub rotation: Rotation,
+    /// Compress files with zstd at this level
+    pub zstd_level: Option<i32>,
+    pub format: EventFormat,
+}
+
+impl JsonlConfig {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            rotation: Rotation::default(),
+            zstd_level: None,
+            format: EventFormat::Ethereum,
+        }
+    }
+}
+
+/// Last block of a zstd frame that has no data, as the encoder writes it:
+/// the block header with the last block flag, raw type and size 0
+const ZSTD_LAST_EMPTY_BLOCK: [u8; 3] = [0x01, 0x00, 0x00];
+
+enum Writer {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Writer {
+    fn new(file: File, zstd_level: Option<i32>) -> io::Result<Self> {
+        let writer = BufWriter::new(file);
+        Ok(match zstd_level {
+            Some(level) => {
+                let mut encoder = zstd::Encoder::new(writer, level)?;
+                // A frame cut off by `Output::rewind` is ended with an empty
+                // block, which is only valid without a checksum
+                encoder.include_checksum(false)?;
+                Writer::Zstd(encoder)
+            }
+            None => Writer::Plain(writer),
+        })
+    }
+
+    fn file(&self) -> &File {
+        match self {
+            Writer::Plain(writer) => writer.get_ref(),
+            Writer::Zstd(writer) => writer.get_ref().get_ref(),
+        }
+    }
+}
+
+/// `.partial` file of a series
+struct Output {
+    path: PathBuf,
+    zstd_level: Option<i32>,
+    writer: Writer,
+    /// Length of the file up to the last written block
+    len: u64,
+    /// Where the current zstd frame starts in the file
+    frame_start: u64,
+    /// Whether writing the last block failed, so that what it wrote has to be
+    /// cut off before the next one is written
+    failed: bool,
+}
+
+impl Output {
+    fn create(path: PathBuf, zstd_level: Option<i32>) -> io::Result<Self> {
+        Ok(Self {
+            writer: Writer::new(File::create(&path)?, zstd_level)?,
+            path,
+            zstd_level,
+            len: 0,
+            frame_start: 0,
+            failed: false,
+        })
+    }
+
+    /// Writes the lines of a block and flushes them, ending a zstd block
+    fn write_block(&mut self, lines: &[String]) -> io::Result<()> {
+        if self.failed {
+            self.rewind()?;
+        }
+        self.failed = true;
+        let writer: &mut dyn Write = match &mut self.writer {
+            Writer::Plain(writer) => writer,
+            Writer::Zstd(writer) => writer,
+        };
+        for line in lines {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        self.len = self.writer.file().metadata()?.len();
+        self.failed = false;
+        Ok(())
+    }
+
+    /// Cuts the file back to the last written block, so that a block that
+    /// failed halfway isn't written twice. The encoder's state can't be
+    /// rewound, so the zstd frame is ended there and a new one is started.
+    fn rewind(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        // Dropping the old writer may still write out buffered data, which is
+        // cut off right after
+        self.writer = Writer::Plain(BufWriter::new(file.try_clone()?));
+        file.set_len(self.len)?;
+        if self.zstd_level.is_some() && self.len > self.frame_start {
+            (&file).write_all(&ZSTD_LAST_EMPTY_BLOCK)?;
+            self.len += ZSTD_LAST_EMPTY_BLOCK.len() as u64;
+            self.frame_start = self.len;
+        }
+        self.writer = Writer::new(file, self.zstd_level)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.failed {
+            self.rewind()?;
+        }
+        let mut writer = match self.writer {
+            Writer::Plain(writer) => writer,
+            Writer::Zstd(writer) => writer.finish()?,
+        };
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    }
+}
+
+/// Writes events to rotating line-delimited JSON files, one file series for
+/// transactions and one for logs.
+///
+/// Files are named `<kind>-<first block>-<last block>.jsonl[.zst]` and listed
+/// in `manifest.json` once finalized. Size limits apply to uncompressed data.
+///
+/// Every block is flushed to the unfinished `.partial` files, so they can be
+/// followed while they're written. Indexing resumes after the last block of
+/// the finalized files, see [`Manifest::checkpoint`], and blocks that are
+/// already in finalized files aren't written again. What a failed block wrote
+/// is cut off before it's retried, so its lines aren't duplicated.
+pub struct JsonlHandler {
+    config: JsonlConfig,
+    manifest: Manifest,
+    transactions: Vec<String>,
+    logs: Vec<String>,
+    transactions_file: Option<Segment<Output>>,
+    logs_file: Option<Segment<Output>>,
+}
+
+impl JsonlHandler {
+    pub fn new(config: JsonlConfig) -> io::Result<Self> {
+        Ok(Self {
+            manifest: Manifest::open(&config.directory)?,
+            config,
+            transactions: Vec::new(),
+            logs: Vec::new(),
+            transactions_file: None,
+            logs_file: None,
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        if self.config.zstd_level.is_some() {
+            "jsonl.zst"
+        } else {
+            "jsonl"
+        }
+    }
+
+    fn write_block(&mut self, kind: Kind, block_height: BlockHeight) -> io::Result<()> {
+        let extension = self.extension();
+        let (lines, segment) = match kind {
+            Kind::Transactions => (&mut self.transactions, &mut self.transactions_file),
+            Kind::Logs => (&mut self.logs, &mut self.logs_file),
+        };
+        if self
+            .manifest
+            .last_block(kind)
+            .is_some_and(|last_block| block_height <= last_block)
+        {
+            lines.clear();
+            return Ok(());
+        }
+        if segment.is_none() {
+            let path = partial_path(&self.manifest, kind, extension, block_height);
+            let output = Output::create(path, self.config.zstd_level)?;
+            *segment = Some(Segment::new(output, kind, extension, block_height));
+        }
+        let file = segment.as_mut().unwrap();
+        file.writer.write_block(lines)?;
+        file.events += lines.len() as u64;
+        file.bytes += lines.iter().map(|line| line.len() as u64 + 1).sum::<u64>();
+        lines.clear();
+        file.end_block(block_height);
+        if file.should_rotate(&self.config.rotation) {
+            segment
+                .take()
+                .unwrap()
+                .finalize(&mut self.manifest, Output::finish)?;
+        }
+        Ok(())
+    }
+
+    fn finalize_files(&mut self) -> io::Result<()> {
+        for segment in [self.transactions_file.take(), self.logs_file.take()]
+            .into_iter()
+            .flatten()
+        {
+            segment.finalize(&mut self.manifest, Output::finish)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for JsonlHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let line = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => {
+                serde_json::to_string(&EthereumTransactionEvent::new(&event, &details))
+            }
+        }?;
+        self.transactions.push(line);
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let line = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => serde_json::to_string(&EthereumLogEvent::new(&event)),
+        }?;
+        self.logs.push(line);
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        self.write_block(Kind::Transactions, block_height)?;
+        self.write_block(Kind::Logs, block_height)?;
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        self.finalize_files()?;
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        Ok(self.manifest.checkpoint())
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::{ClientConfig, Message as _, Offset, TopicPartitionList};
+use serde::Deserialize;
+
+use crate::format::{checksum_address, EthereumLogEvent, EthereumTransactionEvent, EventFormat};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Topic names for each event kind. `{engine}` is replaced with the engine
+/// account id.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KafkaTopics {
+    pub transactions: String,
+    pub logs: String,
+}
+
+impl Default for KafkaTopics {
+    fn default() -> Self {
+        Self {
+            transactions: "aurora.{engine}.transactions".to_owned(),
+            logs: "aurora.{engine}.logs".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    /// `bootstrap.servers`
+    pub brokers: String,
+    /// `transactional.id`, must be unique per indexer instance and stable
+    /// across restarts
+    pub transactional_id: String,
+    pub topics: KafkaTopics,
+    /// Compacted topic the height of each produced block is written to, keyed
+    /// by `transactional_id`. Created if it doesn't exist.
+    pub checkpoint_topic: String,
+    /// Overrides `topics` for specific engine accounts
+    pub engine_topics: HashMap<AccountId, KafkaTopics>,
+    pub format: EventFormat,
+}
+
+impl KafkaConfig {
+    pub fn new(brokers: impl Into<String>) -> Self {
+        Self {
+            brokers: brokers.into(),
+            transactional_id: "aurora-indexer".to_owned(),
+            topics: KafkaTopics::default(),
+            checkpoint_topic: "aurora-indexer.checkpoints".to_owned(),
+            engine_topics: HashMap::new(),
+            format: EventFormat::default(),
+        }
+    }
+
+    fn topics(&self, engine_account_id: &AccountId) -> &KafkaTopics {
+        self.engine_topics
+            .get(engine_account_id)
+            .unwrap_or(&self.topics)
+    }
+}
+
+struct Message {
+    topic: String,
+    /// Address of the transaction sender
+    key: String,
+    payload: String,
+}
+
+/// Produces events to Kafka (or Redpanda), keyed by sender address so that
+/// all transactions of an account land in the same partition in order.
+///
+/// Each block is produced in a Kafka transaction, so consumers with
+/// `isolation.level=read_committed` never see a partially written block. The
+/// block's height is written to the checkpoint topic in the same transaction,
+/// so indexing resumes right after the last committed block.
+pub struct KafkaHandler {
+    producer: FutureProducer,
+    config: KafkaConfig,
+    messages: Vec<Message>,
+    /// Sender of the last transaction, logs are keyed by it
+    last_sender: String,
+}
+
+impl KafkaHandler {
+    pub async fn new(config: KafkaConfig) -> Result<Self, KafkaError> {
+        create_checkpoint_topic(&config).await?;
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("transactional.id", &config.transactional_id)
+            .set("enable.idempotence", "true")
+            .create()?;
+        let initialized = producer.clone();
+        blocking(move || initialized.init_transactions(TRANSACTION_TIMEOUT)).await?;
+        Ok(Self {
+            producer,
+            config,
+            messages: Vec::new(),
+            last_sender: String::new(),
+        })
+    }
+
+    async fn produce_block(&mut self, block_height: BlockHeight) -> Result<(), KafkaError> {
+        self.producer.begin_transaction()?;
+        if let Err(err) = self.produce_messages(block_height).await {
+            let producer = self.producer.clone();
+            blocking(move || producer.abort_transaction(TRANSACTION_TIMEOUT)).await?;
+            return Err(err);
+        }
+        self.messages.clear();
+        Ok(())
+    }
+
+    /// Enqueues all messages and the checkpoint before waiting for any of them
+    /// to be delivered, then commits the transaction
+    async fn produce_messages(&self, block_height: BlockHeight) -> Result<(), KafkaError> {
+        let mut deliveries = Vec::with_capacity(self.messages.len() + 1);
+        for message in &self.messages {
+            let record = FutureRecord::to(&message.topic)
+                .key(&message.key)
+                .payload(&message.payload);
+            deliveries.push(self.producer.send_result(record).map_err(|(err, _)| err)?);
+        }
+        let height = block_height.to_string();
+        let checkpoint = FutureRecord::to(&self.config.checkpoint_topic)
+            .key(&self.config.transactional_id)
+            .payload(&height);
+        deliveries.push(
+            self.producer
+                .send_result(checkpoint)
+                .map_err(|(err, _)| err)?,
+        );
+        for delivery in deliveries {
+            delivery
+                .await
+                .map_err(|_| KafkaError::Canceled)?
+                .map_err(|(err, _)| err)?;
+        }
+        let producer = self.producer.clone();
+        blocking(move || producer.commit_transaction(TRANSACTION_TIMEOUT)).await
+    }
+}
+
+/// Runs a blocking librdkafka call on the blocking thread pool
+async fn blocking<T: Send + 'static>(
+    call: impl FnOnce() -> Result<T, KafkaError> + Send + 'static,
+) -> Result<T, KafkaError> {
+    tokio::task::spawn_blocking(call)
+        .await
+        .map_err(|_| KafkaError::Canceled)?
+}
+
+async fn create_checkpoint_topic(config: &KafkaConfig) -> Result<(), KafkaError> {
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", &config.brokers)
+        .create()?;
+    // The broker's default replication factor
+    let topic = NewTopic::new(&config.checkpoint_topic, 1, TopicReplication::Fixed(-1))
+        .set("cleanup.policy", "compact");
+    for result in admin.create_topics(&[topic], &AdminOptions::new()).await? {
+        match result {
+            Ok(_) | Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+            Err((_, code)) => return Err(KafkaError::AdminOp(code)),
+        }
+    }
+    Ok(())
+}
+
+/// Last height written to the checkpoint topic under `transactional_id`, read
+/// up to the end of each partition
+fn read_checkpoint(config: &KafkaConfig) -> Result<Option<BlockHeight>, KafkaError> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &config.brokers)
+        .set("group.id", &config.transactional_id)
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true")
+        .set("isolation.level", "read_committed")
+        .create()?;
+    let topic = config.checkpoint_topic.as_str();
+    let metadata = consumer.fetch_metadata(Some(topic), TRANSACTION_TIMEOUT)?;
+    let mut assignment = TopicPartitionList::new();
+    let partitions = metadata
+        .topics()
+        .iter()
+        .flat_map(|metadata| metadata.partitions());
+    for partition in partitions {
+        assignment.add_partition_offset(topic, partition.id(), Offset::Beginning)?;
+    }
+    let mut remaining = assignment.count();
+    consumer.assign(&assignment)?;
+
+    let mut height = None;
+    while remaining > 0 {
+        match consumer.poll(TRANSACTION_TIMEOUT) {
+            Some(Ok(message)) => {
+                if message.key() == Some(config.transactional_id.as_bytes()) {
+                    height = message
+                        .payload()
+                        .and_then(|payload| std::str::from_utf8(payload).ok())
+                        .and_then(|payload| payload.parse().ok());
+                }
+            }
+            Some(Err(KafkaError::PartitionEOF(_))) => remaining -= 1,
+            Some(Err(err)) => return Err(err),
+            None => {
+                return Err(KafkaError::MessageConsumption(
+                    RDKafkaErrorCode::OperationTimedOut,
+                ))
+            }
+        }
+    }
+    Ok(height)
+}
+
+#[async_trait]
+impl AuroraEventHandler for KafkaHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let topic = self
+            .config
+            .topics(&details.engine_account_id)
+            .transactions
+            .replace("{engine}", details.engine_account_id.as_str());
+        let key = checksum_address(&event.from);
+        let payload = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => {
+                serde_json::to_string(&EthereumTransactionEvent::new(&event, &details))
+            }
+        }?;
+        self.last_sender = key.clone();
+        self.messages.push(Message {
+            topic,
+            key,
+            payload,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let topic = self
+            .config
+            .topics(&event.engine_account_id)
+            .logs
+            .replace("{engine}", event.engine_account_id.as_str());
+        let payload = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => serde_json::to_string(&EthereumLogEvent::new(&event)),
+        }?;
+        self.messages.push(Message {
+            topic,
+            key: self.last_sender.clone(),
+            payload,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        self.produce_block(block_height).await?;
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.messages.clear();
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let config = self.config.clone();
+        Ok(blocking(move || read_checkpoint(&config)).await?)
+    }
+}
@@ -1,27 +1,592 @@
+#[cfg(any(feature = "jsonl", feature = "parquet"))]
+pub mod archive;
+pub mod backfill;
+pub mod bloom;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse_handler;
+pub mod fanout_handler;
+pub mod filter_handler;
+pub mod format;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod health;
+#[cfg(feature = "jsonl")]
+pub mod jsonl_handler;
+#[cfg(feature = "kafka")]
+pub mod kafka_handler;
+pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats_handler;
+#[cfg(feature = "parquet")]
+pub mod parquet_handler;
+#[cfg(feature = "postgres")]
+pub mod postgres_handler;
 pub mod redis_handler;
+pub mod retry_handler;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "scripting")]
+pub mod script_handler;
+pub mod shutdown;
+pub mod signatures;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_handler;
+pub mod stream_verifier;
+pub mod telemetry;
+#[cfg(feature = "webhook")]
+pub mod webhook_handler;
+#[cfg(feature = "websocket")]
+pub mod websocket_handler;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use aurora_engine_sdk::types::near_account_to_evm_address;
 use aurora_engine_transactions::{EthTransactionKind, NormalizedEthTransaction};
 use aurora_engine_types::borsh;
-use aurora_engine_types::parameters::engine::{CallArgs, SubmitArgs, SubmitResult};
-use aurora_engine_types::types::Wei;
+use aurora_engine_types::parameters::engine::{
+    CallArgs, SubmitArgs, SubmitResult, TransactionStatus as EngineTransactionStatus,
+};
+use aurora_engine_types::types::{Address, Wei};
+use aurora_engine_types::H256;
+use bloom::Bloom;
+use format::EthereumReceipt;
+use health::Health;
 use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
 use inindexer::near_indexer_primitives::views::{ActionView, ExecutionStatusView, ReceiptEnumView};
-use inindexer::near_indexer_primitives::CryptoHash;
-use inindexer::near_indexer_primitives::StreamerMessage;
+use inindexer::near_indexer_primitives::{
+    CryptoHash, IndexerExecutionOutcomeWithReceipt, StreamerMessage,
+};
 use inindexer::{IncompleteTransaction, Indexer, TransactionReceipt};
 use intear_events::events::aurora::transaction::{AuroraTransactionEvent, TransactionStatus};
+use metrics::Metrics;
+use serde::{Deserialize, Serialize};
+use shutdown::Shutdown;
+use signatures::SignatureDatabase;
+
+/// Error returned by handlers, e.g. when a sink is unreachable
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
 
 #[async_trait]
 pub trait AuroraEventHandler: Send + Sync {
-    async fn handle_transaction(&mut self, event: AuroraTransactionEvent);
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError>;
+
+    /// Called for each log of a transaction, after its `handle_transaction`
+    async fn handle_log(&mut self, _event: AuroraLogEvent) -> Result<(), HandlerError> {
+        Ok(())
+    }
+
+    /// Called at the end of each block for each engine account, after the
+    /// block's transactions and logs and before `flush_events`
+    async fn handle_block(&mut self, _event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        Ok(())
+    }
 
-    /// Called after each block
-    async fn flush_events(&mut self, block_height: BlockHeight);
+    /// Called after each block. When it fails, the handler keeps the events
+    /// it couldn't write, so that calling it again for the same block writes
+    /// them without the block's events being passed again.
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError>;
+
+    /// Called instead of retrying `flush_events` when a block is skipped
+    /// after its flush failed. Drops the events the handler kept for it, so
+    /// that they aren't written together with the next block.
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        Ok(())
+    }
+
+    /// Called once when indexing stops, after the last `flush_events`
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        Ok(())
+    }
+
+    /// Last block the handler flushed, if it stores this together with the
+    /// events so that indexing can resume right after it
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        Ok(None)
+    }
 }
 
-pub struct AuroraIndexer<T: AuroraEventHandler + Send + Sync + 'static>(pub T);
+#[async_trait]
+impl<T: AuroraEventHandler + ?Sized> AuroraEventHandler for Box<T> {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        (**self).handle_transaction(event, details).await
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        (**self).handle_log(event).await
+    }
+
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        (**self).handle_block(event).await
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        (**self).flush_events(block_height).await
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        (**self).discard_events().await
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        (**self).finish().await
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        (**self).last_flushed_height().await
+    }
+}
+
+/// Decoded information about a transaction that doesn't fit into [`AuroraTransactionEvent`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionDetails {
+    /// Account of the engine that executed the transaction
+    pub engine_account_id: AccountId,
+    /// Transaction hash, `aurora_tx_hash` in the event is its `0x` hex form
+    pub tx_hash: H256,
+    /// Best-match signature of the called function, if the selector is known
+    pub signature: Option<String>,
+    pub gas_used: u64,
+    /// Hash of the Aurora block, if the engine's chain id is known
+    pub block_hash: Option<H256>,
+    /// Position of the transaction in the Aurora block, in execution order
+    pub transaction_index: usize,
+    /// Gas used by this and the preceding transactions of the Aurora block
+    pub cumulative_gas_used: u64,
+    /// Aurora block-wide index of the transaction's first log
+    pub first_log_index: usize,
+    /// Ethereum receipt of the transaction within its Aurora block
+    pub receipt: EthereumReceipt,
+}
+
+/// Position of a transaction in its Aurora block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockPosition {
+    pub block_hash: Option<H256>,
+    pub transaction_index: usize,
+    /// Gas used by this and the preceding transactions
+    pub cumulative_gas_used: u64,
+    /// Block-wide index of the transaction's first log
+    pub first_log_index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuroraLogEvent {
+    pub block_height: BlockHeight,
+    pub block_timestamp_nanosec: u128,
+    pub transaction_id: CryptoHash,
+    pub receipt_id: CryptoHash,
+    pub aurora_tx_hash: String,
+    pub engine_account_id: AccountId,
+    /// Index of the log within its transaction
+    pub log_index: usize,
+    pub address: Address,
+    pub topics: Vec<[u8; 32]>,
+    pub data: Vec<u8>,
+    /// Best-match signature of the event, if the first topic is known
+    pub signature: Option<String>,
+}
+
+impl AuroraLogEvent {
+    pub const ID: &'static str = "aurora_log";
+}
+
+/// EVM block of an engine account at a NEAR block height. The engine produces
+/// one for every height, so blocks without transactions are included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuroraBlockEvent {
+    pub block_height: BlockHeight,
+    pub block_timestamp_nanosec: u128,
+    pub engine_account_id: AccountId,
+    pub chain_id: Option<u64>,
+    /// See [`aurora_block_hash`], `None` if the engine's chain id is unknown
+    pub block_hash: Option<H256>,
+    /// Transactions emitted for the block, submits that don't decode aren't
+    /// counted and their gas isn't included
+    pub transaction_count: usize,
+    pub gas_used: u64,
+    /// Bloom of all logs in the block
+    pub logs_bloom: Bloom,
+}
+
+impl AuroraBlockEvent {
+    pub const ID: &'static str = "aurora_block";
+}
+
+/// Chain id of Aurora mainnet, whose engine runs on the `aurora` account
+pub const AURORA_MAINNET_CHAIN_ID: u64 = 1313161554;
+
+/// Hash the engine gives the block at `block_height`:
+/// `sha256(0x00 ‖ chain id as 32 bytes ‖ engine account ‖ height as 8 bytes)`,
+/// big-endian
+pub fn aurora_block_hash(
+    chain_id: u64,
+    engine_account_id: &AccountId,
+    block_height: BlockHeight,
+) -> H256 {
+    let mut data = Vec::with_capacity(1 + 32 + engine_account_id.len() + 8);
+    data.push(0);
+    data.extend_from_slice(&[0; 24]);
+    data.extend_from_slice(&chain_id.to_be_bytes());
+    data.extend_from_slice(engine_account_id.as_bytes());
+    data.extend_from_slice(&block_height.to_be_bytes());
+    aurora_engine_sdk::sha256(&data)
+}
+
+/// Aurora blocks of a NEAR block and the positions of their submits, by
+/// receipt
+struct AuroraBlocks {
+    block_height: BlockHeight,
+    blocks: Vec<AuroraBlockEvent>,
+    positions: HashMap<CryptoHash, BlockPosition>,
+}
+
+/// Indexer that passes the Aurora events of each block to `handler`.
+///
+/// Created with [`AuroraIndexer::new`] and configured with the `with_*`
+/// methods, the handler is reachable as the `handler` field.
+pub struct AuroraIndexer<T: AuroraEventHandler + Send + Sync + 'static> {
+    pub handler: T,
+    signatures: SignatureDatabase,
+    engine_account_ids: Vec<AccountId>,
+    chain_ids: HashMap<AccountId, u64>,
+    shutdown: Shutdown,
+    progress_file: Option<PathBuf>,
+    last_flushed_height: Option<BlockHeight>,
+    metrics: Metrics,
+    health: Health,
+    /// Aurora blocks of the current block, computed on first use
+    current_blocks: Option<AuroraBlocks>,
+}
+
+impl<T: AuroraEventHandler + Send + Sync + 'static> AuroraIndexer<T> {
+    pub fn new(handler: T) -> Self {
+        Self {
+            handler,
+            signatures: SignatureDatabase::bundled(),
+            engine_account_ids: vec!["aurora".parse().unwrap()],
+            chain_ids: HashMap::from([("aurora".parse().unwrap(), AURORA_MAINNET_CHAIN_ID)]),
+            shutdown: Shutdown::new(),
+            progress_file: None,
+            last_flushed_height: None,
+            metrics: Metrics::new(),
+            health: Health::default(),
+            current_blocks: None,
+        }
+    }
+
+    /// Engine accounts to index, `aurora` by default. Silos are separate
+    /// engine deployments and can be added here.
+    pub fn with_engine_account_ids(mut self, engine_account_ids: Vec<AccountId>) -> Self {
+        self.engine_account_ids = engine_account_ids;
+        self
+    }
+
+    /// Chain id of an engine account, needed for its block hashes. Only
+    /// `aurora` is known by default.
+    pub fn with_chain_id(mut self, engine_account_id: AccountId, chain_id: u64) -> Self {
+        self.chain_ids.insert(engine_account_id, chain_id);
+        self
+    }
+
+    pub fn with_signatures(mut self, signatures: SignatureDatabase) -> Self {
+        self.signatures = signatures;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// File the height of the last flushed block is written to after each
+    /// block, see [`load_progress`]
+    pub fn with_progress_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.progress_file = Some(path.into());
+        self
+    }
+
+    pub fn last_flushed_height(&self) -> Option<BlockHeight> {
+        self.last_flushed_height
+    }
+
+    #[tracing::instrument(name = "flush", skip_all)]
+    async fn flush_block(&mut self, block: &StreamerMessage) -> Result<(), String> {
+        let block_height = block.block.header.height;
+        let started = Instant::now();
+        if let Err(err) = self.handler.flush_events(block_height).await {
+            self.health.sink_failed(&err);
+            return Err(format!("Failed to flush events: {err}"));
+        }
+        self.metrics
+            .flush_duration
+            .observe(started.elapsed().as_secs_f64());
+        self.last_flushed_height = Some(block_height);
+        self.metrics.block_height.set(block_height as i64);
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(block.block.header.timestamp_nanosec);
+        if let Ok(lag) = SystemTime::now().duration_since(timestamp) {
+            self.metrics.lag_seconds.set(lag.as_secs_f64());
+        }
+        self.health.block_flushed(block_height, timestamp);
+        if let Some(path) = &self.progress_file {
+            save_progress(path, block_height)
+                .map_err(|err| format!("Failed to save progress to {}: {err}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "submit",
+        skip_all,
+        fields(
+            block_height = block.block.header.height,
+            receipt_id = %receipt.receipt.receipt.receipt_id,
+            aurora_tx_hash = tracing::field::Empty,
+        )
+    )]
+    async fn handle_submit(
+        &mut self,
+        tx_data: &[u8],
+        receipt: &TransactionReceipt,
+        transaction: &IncompleteTransaction,
+        block: &StreamerMessage,
+    ) -> Result<(), HandlerError> {
+        let Ok(transaction_kind) = EthTransactionKind::try_from(tx_data) else {
+            self.decode_failed("transaction", receipt);
+            return Ok(());
+        };
+        let Ok(aurora_transaction) = NormalizedEthTransaction::try_from(transaction_kind) else {
+            self.decode_failed("normalize", receipt);
+            return Ok(());
+        };
+        let ExecutionStatusView::SuccessValue(value) =
+            &receipt.receipt.execution_outcome.outcome.status
+        else {
+            return Ok(());
+        };
+        let Ok(result) = borsh::de::from_slice::<SubmitResult>(value) else {
+            self.decode_failed("result", receipt);
+            return Ok(());
+        };
+        let tx_hash = aurora_engine_sdk::keccak(tx_data);
+        tracing::Span::current().record(
+            "aurora_tx_hash",
+            tracing::field::display(format!("{tx_hash:#x}")),
+        );
+        let tx = AuroraTransactionEvent {
+            block_height: block.block.header.height,
+            block_timestamp_nanosec: block.block.header.timestamp_nanosec as u128,
+            transaction_id: transaction.transaction.transaction.hash,
+            receipt_id: receipt.receipt.receipt.receipt_id,
+            chain_id: aurora_transaction.chain_id,
+            aurora_tx_hash: format!("{tx_hash:#x}"),
+            from: aurora_transaction.address,
+            to: aurora_transaction.to,
+            value: aurora_transaction.value,
+            input: aurora_transaction.data,
+            status: match result.status {
+                EngineTransactionStatus::Succeed(v) => TransactionStatus::Succeed(v),
+                EngineTransactionStatus::Revert(v) => TransactionStatus::Revert(v),
+                EngineTransactionStatus::OutOfGas => TransactionStatus::OutOfGas,
+                EngineTransactionStatus::OutOfFund => TransactionStatus::OutOfFund,
+                EngineTransactionStatus::OutOfOffset => TransactionStatus::OutOfOffset,
+                EngineTransactionStatus::CallTooDeep => TransactionStatus::CallTooDeep,
+                _ => TransactionStatus::Revert("".as_bytes().to_vec()), // there are more error types added since the indexer was created. Since it's a dynamic field, and mostly no one cares about the specifics of the failure, we just make a backwards compatible default
+            },
+        };
+        tracing::debug!(
+            status = format::status_name(&tx.status),
+            "Decoded transaction"
+        );
+        let logs = result
+            .logs
+            .into_iter()
+            .enumerate()
+            .map(|(log_index, log)| AuroraLogEvent {
+                block_height: block.block.header.height,
+                block_timestamp_nanosec: block.block.header.timestamp_nanosec as u128,
+                transaction_id: transaction.transaction.transaction.hash,
+                receipt_id: receipt.receipt.receipt.receipt_id,
+                aurora_tx_hash: format!("{tx_hash:#x}"),
+                engine_account_id: receipt.receipt.receipt.receiver_id.clone(),
+                log_index,
+                signature: self
+                    .signatures
+                    .event_signature(&log.topics)
+                    .map(ToOwned::to_owned),
+                address: log.address,
+                topics: log.topics,
+                data: log.data,
+            })
+            .collect::<Vec<_>>();
+        let receipt_id = receipt.receipt.receipt.receipt_id;
+        let Some(position) = self
+            .aurora_blocks(block)
+            .positions
+            .get(&receipt_id)
+            .copied()
+        else {
+            return Err(format!("No position in the Aurora block for receipt {receipt_id}").into());
+        };
+        let details = TransactionDetails {
+            engine_account_id: receipt.receipt.receipt.receiver_id.clone(),
+            tx_hash,
+            signature: self
+                .signatures
+                .function_signature(&tx.input)
+                .map(ToOwned::to_owned),
+            gas_used: result.gas_used,
+            block_hash: position.block_hash,
+            transaction_index: position.transaction_index,
+            cumulative_gas_used: position.cumulative_gas_used,
+            first_log_index: position.first_log_index,
+            receipt: EthereumReceipt::new(&tx, tx_hash, result.gas_used, position, &logs),
+        };
+        let engine_account_id = receipt.receipt.receipt.receiver_id.as_str();
+        self.handler.handle_transaction(tx, details).await?;
+        self.metrics
+            .events
+            .with_label_values(&["transaction", engine_account_id])
+            .inc();
+        for log in logs {
+            self.handler.handle_log(log).await?;
+            self.metrics
+                .events
+                .with_label_values(&["log", engine_account_id])
+                .inc();
+        }
+        Ok(())
+    }
+
+    /// Aurora blocks of `block`, with the transactions in the order the
+    /// receipts were executed in. Computed once per block.
+    fn aurora_blocks(&mut self, block: &StreamerMessage) -> &AuroraBlocks {
+        let block_height = block.block.header.height;
+        if !self
+            .current_blocks
+            .as_ref()
+            .is_some_and(|current| current.block_height == block_height)
+        {
+            self.current_blocks = Some(self.compute_aurora_blocks(block));
+        }
+        self.current_blocks.as_ref().expect("Computed above")
+    }
+
+    fn compute_aurora_blocks(&self, block: &StreamerMessage) -> AuroraBlocks {
+        let block_height = block.block.header.height;
+        let mut blocks = self
+            .engine_account_ids
+            .iter()
+            .map(|engine_account_id| {
+                let chain_id = self.chain_ids.get(engine_account_id).copied();
+                AuroraBlockEvent {
+                    block_height,
+                    block_timestamp_nanosec: block.block.header.timestamp_nanosec as u128,
+                    engine_account_id: engine_account_id.clone(),
+                    chain_id,
+                    block_hash: chain_id.map(|chain_id| {
+                        aurora_block_hash(chain_id, engine_account_id, block_height)
+                    }),
+                    transaction_count: 0,
+                    gas_used: 0,
+                    logs_bloom: Bloom::default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut log_counts = vec![0; blocks.len()];
+        let mut positions = HashMap::new();
+        for outcome in block
+            .shards
+            .iter()
+            .flat_map(|shard| &shard.receipt_execution_outcomes)
+        {
+            let Some(i) = self
+                .engine_account_ids
+                .iter()
+                .position(|account_id| *account_id == outcome.receipt.receiver_id)
+            else {
+                continue;
+            };
+            let Some(result) = submit_result(outcome) else {
+                continue;
+            };
+            let aurora_block = &mut blocks[i];
+            aurora_block.gas_used += result.gas_used;
+            for log in &result.logs {
+                aurora_block
+                    .logs_bloom
+                    .accrue_log(&log.address, &log.topics);
+            }
+            positions.insert(
+                outcome.receipt.receipt_id,
+                BlockPosition {
+                    block_hash: aurora_block.block_hash,
+                    transaction_index: aurora_block.transaction_count,
+                    cumulative_gas_used: aurora_block.gas_used,
+                    first_log_index: log_counts[i],
+                },
+            );
+            aurora_block.transaction_count += 1;
+            log_counts[i] += result.logs.len();
+        }
+        AuroraBlocks {
+            block_height,
+            blocks,
+            positions,
+        }
+    }
+
+    async fn handle_aurora_blocks(&mut self, block: &StreamerMessage) -> Result<(), String> {
+        self.aurora_blocks(block);
+        let Some(aurora_blocks) = self.current_blocks.take() else {
+            return Ok(());
+        };
+        for event in aurora_blocks.blocks {
+            let engine_account_id = event.engine_account_id.clone();
+            self.handler
+                .handle_block(event)
+                .await
+                .map_err(|err| format!("Failed to handle block: {err}"))?;
+            self.metrics
+                .events
+                .with_label_values(&["block", engine_account_id.as_str()])
+                .inc();
+        }
+        Ok(())
+    }
+
+    fn decode_failed(&self, stage: &str, receipt: &TransactionReceipt) {
+        tracing::warn!(
+            stage,
+            receipt_id = %receipt.receipt.receipt.receipt_id,
+            "Failed to decode submit"
+        );
+        self.metrics
+            .decode_failures
+            .with_label_values(&[stage])
+            .inc();
+    }
+}
 
 #[async_trait]
 impl<T: AuroraEventHandler + Send + Sync + 'static> Indexer for AuroraIndexer<T> {
@@ -33,7 +598,11 @@ impl<T: AuroraEventHandler + Send + Sync + 'static> Indexer for AuroraIndexer<T>
         transaction: &IncompleteTransaction,
         block: &StreamerMessage,
     ) -> Result<(), Self::Error> {
-        if receipt.receipt.receipt.receiver_id == "aurora" {
+        self.shutdown.block_started().await;
+        if self
+            .engine_account_ids
+            .contains(&receipt.receipt.receipt.receiver_id)
+        {
             if let ReceiptEnumView::Action { actions, .. } = &receipt.receipt.receipt.receipt {
                 for action in actions {
                     if let ActionView::FunctionCall {
@@ -43,104 +612,21 @@ impl<T: AuroraEventHandler + Send + Sync + 'static> Indexer for AuroraIndexer<T>
                         match method_name.as_str() {
                             "submit_with_args" => {
                                 if let Ok(args) = borsh::de::from_slice::<SubmitArgs>(args) {
-                                    if let Ok(transaction_kind) =
-                                        EthTransactionKind::try_from(args.tx_data.as_slice())
-                                    {
-                                        if let Ok(aurora_transaction) =
-                                            NormalizedEthTransaction::try_from(transaction_kind)
-                                        {
-                                            if let ExecutionStatusView::SuccessValue(value) =
-                                                &receipt.receipt.execution_outcome.outcome.status
-                                            {
-                                                if let Ok(result) =
-                                                    borsh::de::from_slice::<SubmitResult>(value)
-                                                {
-                                                    let tx_hash =
-                                                        aurora_engine_sdk::keccak(&args.tx_data);
-                                                    let tx = AuroraTransactionEvent {
-                                                        block_height: block.block.header.height,
-                                                        block_timestamp_nanosec: block
-                                                            .block
-                                                            .header
-                                                            .timestamp_nanosec
-                                                            as u128,
-                                                        transaction_id: transaction
-                                                            .transaction
-                                                            .transaction
-                                                            .hash,
-                                                        receipt_id: receipt.receipt.receipt.receipt_id,
-                                                        chain_id: aurora_transaction.chain_id,
-                                                        aurora_tx_hash: tx_hash.to_string(),
-                                                        from: aurora_transaction.address,
-                                                        to: aurora_transaction
-                                                            .to,
-                                                        value: aurora_transaction.value,
-                                                        input: aurora_transaction.data,
-                                                        status: match result.status {
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::Succeed(v) => TransactionStatus::Succeed(v),
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::Revert(v) => TransactionStatus::Revert(v),
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::OutOfGas => TransactionStatus::OutOfGas,
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::OutOfFund => TransactionStatus::OutOfFund,
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::OutOfOffset => TransactionStatus::OutOfOffset,
-                                                            aurora_engine_types::parameters::engine::TransactionStatus::CallTooDeep => TransactionStatus::CallTooDeep,
-                                                            _ => TransactionStatus::Revert("".as_bytes().to_vec()), // there are more error types added since the indexer was created. Since it's a dynamic field, and mostly no one cares about the specifics of the failure, we just make a backwards compatible default
-                                                        },
-                                                    };
-                                                    self.0.handle_transaction(tx).await;
-                                                }
-                                            }
-                                        }
-                                    }
+                                    self.handle_submit(&args.tx_data, receipt, transaction, block)
+                                        .await
+                                        .map_err(|err| {
+                                            format!("Failed to handle transaction: {err}")
+                                        })?;
+                                } else {
+                                    self.decode_failed("submit_args", receipt);
                                 }
                             }
                             "submit" => {
-                                if let Ok(transaction_kind) =
-                                    EthTransactionKind::try_from(args.as_slice())
-                                {
-                                    if let Ok(aurora_transaction) =
-                                        NormalizedEthTransaction::try_from(transaction_kind)
-                                    {
-                                        if let ExecutionStatusView::SuccessValue(value) =
-                                            &receipt.receipt.execution_outcome.outcome.status
-                                        {
-                                            if let Ok(result) =
-                                                borsh::de::from_slice::<SubmitResult>(value)
-                                            {
-                                                let tx_hash = aurora_engine_sdk::keccak(args);
-                                                let tx = AuroraTransactionEvent {
-                                                    block_height: block.block.header.height,
-                                                    block_timestamp_nanosec: block
-                                                        .block
-                                                        .header
-                                                        .timestamp_nanosec
-                                                        as u128,
-                                                    transaction_id: transaction
-                                                        .transaction
-                                                        .transaction
-                                                        .hash,
-                                                    receipt_id: receipt.receipt.receipt.receipt_id,
-                                                    chain_id: aurora_transaction.chain_id,
-                                                    aurora_tx_hash: tx_hash.to_string(),
-                                                    from: aurora_transaction.address,
-                                                    to: aurora_transaction
-                                                        .to,
-                                                    value: aurora_transaction.value,
-                                                    input: aurora_transaction.data,
-                                                    status: match result.status {
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::Succeed(v) => TransactionStatus::Succeed(v),
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::Revert(v) => TransactionStatus::Revert(v),
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::OutOfGas => TransactionStatus::OutOfGas,
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::OutOfFund => TransactionStatus::OutOfFund,
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::OutOfOffset => TransactionStatus::OutOfOffset,
-                                                        aurora_engine_types::parameters::engine::TransactionStatus::CallTooDeep => TransactionStatus::CallTooDeep,
-                                                        _ => TransactionStatus::Revert("".as_bytes().to_vec()),
-                                                    },
-                                                };
-                                                self.0.handle_transaction(tx).await;
-                                            }
-                                        }
-                                    }
-                                }
+                                self.handle_submit(args, receipt, transaction, block)
+                                    .await
+                                    .map_err(|err| {
+                                        format!("Failed to handle transaction: {err}")
+                                    })?;
                             }
                             "call" => {
                                 if let Some(call_args) = CallArgs::deserialize(args) {
@@ -178,12 +664,71 @@ impl<T: AuroraEventHandler + Send + Sync + 'static> Indexer for AuroraIndexer<T>
         Ok(())
     }
 
+    #[tracing::instrument(name = "block", skip_all, fields(block_height = block.block.header.height))]
     async fn process_block_end(&mut self, block: &StreamerMessage) -> Result<(), Self::Error> {
-        self.0.flush_events(block.block.header.height).await;
-        Ok(())
+        self.shutdown.block_started().await;
+        let mut result = self.handle_aurora_blocks(block).await;
+        if result.is_ok() {
+            result = self.flush_block(block).await;
+        }
+        self.shutdown.block_finished();
+        result
+    }
+}
+
+/// Result of a `submit` or `submit_with_args` receipt, if it executed and its
+/// transaction decodes, i.e. if a transaction event is emitted for it
+fn submit_result(outcome: &IndexerExecutionOutcomeWithReceipt) -> Option<SubmitResult> {
+    let ReceiptEnumView::Action { actions, .. } = &outcome.receipt.receipt else {
+        return None;
+    };
+    let is_emitted = actions.iter().any(|action| {
+        let ActionView::FunctionCall {
+            method_name, args, ..
+        } = action
+        else {
+            return false;
+        };
+        match method_name.as_str() {
+            "submit" => transaction_decodes(args),
+            "submit_with_args" => borsh::de::from_slice::<SubmitArgs>(args)
+                .is_ok_and(|args| transaction_decodes(&args.tx_data)),
+            _ => false,
+        }
+    });
+    match &outcome.execution_outcome.outcome.status {
+        ExecutionStatusView::SuccessValue(value) if is_emitted => borsh::de::from_slice(value).ok(),
+        _ => None,
+    }
+}
+
+/// Whether `handle_submit` can decode the transaction
+fn transaction_decodes(tx_data: &[u8]) -> bool {
+    EthTransactionKind::try_from(tx_data)
+        .is_ok_and(|transaction_kind| NormalizedEthTransaction::try_from(transaction_kind).is_ok())
+}
+
+/// Height of the last flushed block, as written by
+/// [`AuroraIndexer::with_progress_file`], or `None` if the file doesn't exist
+pub fn load_progress(path: impl AsRef<Path>) -> std::io::Result<Option<BlockHeight>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
     }
 }
 
+fn save_progress(path: &Path, block_height: BlockHeight) -> std::io::Result<()> {
+    // Renamed into place, so that the file is never seen half-written
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, block_height.to_string())?;
+    std::fs::rename(temporary, path)
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct EventContext {
     pub transaction_id: CryptoHash,
@@ -1,7 +1,20 @@
 #[cfg(test)]
 mod tests;
 
+use aurora_indexer::backfill::BackfillConfig;
+use aurora_indexer::fanout_handler::{FailurePolicy, FanOutHandler};
+use aurora_indexer::filter_handler::FilterHandler;
+use aurora_indexer::format::EventFormat;
+use aurora_indexer::health::Health;
+use aurora_indexer::metrics::Metrics;
 use aurora_indexer::redis_handler;
+use aurora_indexer::retry_handler::{RetryConfig, RetryHandler};
+use aurora_indexer::shutdown::Shutdown;
+use aurora_indexer::signatures::SignatureDatabase;
+use aurora_indexer::stream_verifier::{StreamReport, StreamVerifier};
+use aurora_indexer::telemetry::TelemetryConfig;
+use aurora_indexer::{AuroraEventHandler, AuroraIndexer};
+use inindexer::near_indexer_primitives::types::BlockHeight;
 use inindexer::neardata::NeardataProvider;
 use inindexer::{
     run_indexer, AutoContinue, BlockRange, IndexerOptions, PreprocessTransactionsSettings,
@@ -12,53 +25,571 @@ use redis_handler::PushToRedisStream;
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-    simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .with_module_level("inindexer::performance", log::LevelFilter::Debug)
-        .init()
-        .unwrap();
+    let telemetry =
+        aurora_indexer::telemetry::init(TelemetryConfig::from_env().expect("Invalid $LOG_FORMAT"))
+            .expect("Failed to set up logging");
 
-    let client = redis::Client::open(
-        std::env::var("REDIS_URL").expect("No $REDIS_URL environment variable set"),
-    )
-    .unwrap();
-    let connection = ConnectionManager::new(client).await.unwrap();
-
-    let mut indexer =
-        aurora_indexer::AuroraIndexer(PushToRedisStream::new(connection, 10_000).await);
-
-    run_indexer(
-        &mut indexer,
-        NeardataProvider::mainnet(),
-        IndexerOptions {
-            preprocess_transactions: Some(PreprocessTransactionsSettings {
-                prefetch_blocks: if cfg!(debug_assertions) { 0 } else { 100 },
-                postfetch_blocks: 0,
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let filter_file = take_option(&mut args, "--filter-file").or(std::env::var("FILTER_FILE").ok());
+    let filter_json = take_option(&mut args, "--filter");
+    let filter = filter_file
+        .map(Filter::File)
+        .or(filter_json.map(Filter::Inline));
+
+    let metrics = Metrics::new();
+    let health = Health::new(std::time::Duration::from_secs(
+        std::env::var("READY_MAX_BLOCK_AGE_SECS")
+            .map(|age| age.parse().expect("Invalid $READY_MAX_BLOCK_AGE_SECS"))
+            .unwrap_or(120),
+    ));
+    if let Ok(address) = std::env::var("HTTP_ADDRESS") {
+        serve_http(&address, metrics.clone(), health.clone()).await;
+    }
+
+    match args.first().map(String::as_str) {
+        Some("backfill") => backfill(&args[1..], filter, metrics, health).await,
+        Some("verify") => verify(&mut args[1..].to_vec()).await,
+        _ => index(&args, filter, metrics, health).await,
+    }
+    telemetry.shutdown();
+}
+
+/// Indexes new blocks, or the range in `args`
+async fn index(args: &[String], filter: Option<Filter>, metrics: Metrics, health: Health) {
+    let mut indexer = new_indexer(filter, metrics, health).await;
+    let range = if !args.is_empty() {
+        // For debugging
+        let msg = "Usage: `indexer [--filter-file <path> | --filter <json>] [start-block end-block]` or `indexer backfill|verify ...`";
+        BlockRange::Range {
+            start_inclusive: block_height(args.first(), msg),
+            end_exclusive: Some(block_height(args.get(1), msg)),
+        }
+    } else {
+        let progress_file =
+            std::env::var("PROGRESS_FILE").unwrap_or_else(|_| "last-flushed-block.txt".to_owned());
+        // The sink's own checkpoint is written together with the events, the
+        // progress file is only a fallback for sinks that don't have one
+        let last_flushed_height = match indexer
+            .handler
+            .last_flushed_height()
+            .await
+            .expect("Failed to read the sink's checkpoint")
+        {
+            Some(height) => Some(height),
+            None => aurora_indexer::load_progress(&progress_file).unwrap_or_else(|err| {
+                panic!("Failed to read progress from {progress_file}: {err}")
             }),
-            ..IndexerOptions::default_with_range(if std::env::args().len() > 1 {
-                // For debugging
-                let msg = "Usage: `indexer` or `indexer [start-block] [end-block]`";
+        };
+        indexer = indexer.with_progress_file(progress_file);
+        match last_flushed_height {
+            Some(height) => {
+                tracing::info!("Resuming after block {height}");
                 BlockRange::Range {
-                    start_inclusive: std::env::args()
-                        .nth(1)
-                        .expect(msg)
-                        .replace(['_', ',', ' ', '.'], "")
-                        .parse()
-                        .expect(msg),
-                    end_exclusive: Some(
-                        std::env::args()
-                            .nth(2)
-                            .expect(msg)
-                            .replace(['_', ',', ' ', '.'], "")
-                            .parse()
-                            .expect(msg),
-                    ),
+                    start_inclusive: height + 1,
+                    end_exclusive: None,
                 }
-            } else {
-                BlockRange::AutoContinue(AutoContinue::default())
-            })
+            }
+            None => BlockRange::AutoContinue(AutoContinue::default()),
+        }
+    };
+
+    let shutdown = Shutdown::new();
+    indexer = indexer.with_shutdown(shutdown.clone());
+    let timeout = std::time::Duration::from_secs(
+        std::env::var("SHUTDOWN_TIMEOUT_SECS")
+            .map(|timeout| timeout.parse().expect("Invalid $SHUTDOWN_TIMEOUT_SECS"))
+            .unwrap_or(30),
+    );
+    tokio::spawn(handle_signals(shutdown.clone(), timeout));
+
+    tokio::select! {
+        result = run_indexer(
+            &mut indexer,
+            NeardataProvider::mainnet(),
+            IndexerOptions {
+                preprocess_transactions: Some(PreprocessTransactionsSettings {
+                    prefetch_blocks: if cfg!(debug_assertions) { 0 } else { 100 },
+                    postfetch_blocks: 0,
+                }),
+                ..IndexerOptions::default_with_range(range)
+            },
+        ) => result.expect("Indexer run failed"),
+        () = shutdown.stopped() => tracing::info!(
+            "Stopped after block {:?}",
+            indexer.last_flushed_height()
+        ),
+    }
+
+    indexer
+        .handler
+        .finish()
+        .await
+        .expect("Failed to finish writing events");
+}
+
+/// `--filter-file` or `--filter`
+#[derive(Clone)]
+enum Filter {
+    File(String),
+    Inline(String),
+}
+
+/// Indexer with the sinks, retries, filter and signatures configured in the
+/// environment
+async fn new_indexer(
+    filter: Option<Filter>,
+    metrics: Metrics,
+    health: Health,
+) -> AuroraIndexer<Box<dyn AuroraEventHandler>> {
+    let mut signatures = SignatureDatabase::bundled();
+    if let Ok(paths) = std::env::var("SIGNATURE_FILES") {
+        for path in paths.split(',').filter(|path| !path.is_empty()) {
+            signatures
+                .load(path)
+                .unwrap_or_else(|err| panic!("Failed to load signatures from {path}: {err}"));
+        }
+    }
+
+    let sinks = std::env::var("SINK").unwrap_or_else(|_| "redis".to_owned());
+    let handler: Box<dyn AuroraEventHandler> = if sinks.contains(',') {
+        let mut handler = FanOutHandler::new();
+        for name in sinks.split(',') {
+            handler = handler.with_handler(name, sink(name, &metrics).await, failure_policy(name));
+        }
+        Box::new(handler)
+    } else {
+        sink(&sinks, &metrics).await
+    };
+
+    let mut retry = RetryConfig::default();
+    if let Ok(max_attempts) = std::env::var("RETRY_MAX_ATTEMPTS") {
+        retry.max_attempts = Some(max_attempts.parse().expect("Invalid $RETRY_MAX_ATTEMPTS"));
+    }
+    let handler = RetryHandler::new(handler, retry).with_health(health.clone());
+
+    let handler: Box<dyn AuroraEventHandler> = match filter {
+        Some(Filter::File(path)) => Box::new(
+            FilterHandler::from_file(handler, &path)
+                .unwrap_or_else(|err| panic!("Failed to load filter from {path}: {err}")),
+        ),
+        Some(Filter::Inline(filter)) => Box::new(FilterHandler::new(
+            handler,
+            serde_json::from_str(&filter).expect("Invalid --filter"),
+        )),
+        None => Box::new(handler),
+    };
+
+    let mut indexer = AuroraIndexer::new(handler)
+        .with_signatures(signatures)
+        .with_metrics(metrics)
+        .with_health(health);
+    if let Ok(engine_account_ids) = std::env::var("ENGINE_ACCOUNT_IDS") {
+        indexer = indexer.with_engine_account_ids(
+            engine_account_ids
+                .split(',')
+                .map(|account_id| account_id.parse().expect("Invalid $ENGINE_ACCOUNT_IDS"))
+                .collect(),
+        );
+    }
+    if let Ok(chain_ids) = std::env::var("ENGINE_CHAIN_IDS") {
+        for entry in chain_ids.split(',') {
+            let (account_id, chain_id) = entry
+                .split_once('=')
+                .expect("Invalid $ENGINE_CHAIN_IDS, expected account=chain_id");
+            indexer = indexer.with_chain_id(
+                account_id.parse().expect("Invalid $ENGINE_CHAIN_IDS"),
+                chain_id.parse().expect("Invalid $ENGINE_CHAIN_IDS"),
+            );
+        }
+    }
+    indexer
+}
+
+/// Sinks that accept blocks in any order and ignore duplicates
+const BACKFILL_SINKS: &[&str] = &["postgres", "sqlite", "clickhouse"];
+
+async fn backfill(args: &[String], filter: Option<Filter>, metrics: Metrics, health: Health) {
+    let msg = "Usage: `indexer backfill start-block end-block`";
+    let mut config = BackfillConfig::new(
+        block_height(args.first(), msg),
+        block_height(args.get(1), msg),
+        std::env::var("BACKFILL_STATE").unwrap_or_else(|_| "backfill-state.json".to_owned()),
+    );
+    if let Ok(workers) = std::env::var("BACKFILL_WORKERS") {
+        config.workers = workers.parse().expect("Invalid $BACKFILL_WORKERS");
+    }
+    if let Ok(chunk_size) = std::env::var("BACKFILL_CHUNK_SIZE") {
+        config.chunk_size = chunk_size.parse().expect("Invalid $BACKFILL_CHUNK_SIZE");
+    }
+    let sinks = std::env::var("SINK").unwrap_or_else(|_| "redis".to_owned());
+    if let Some(sink) = sinks.split(',').find(|sink| !BACKFILL_SINKS.contains(sink)) {
+        panic!(
+            "The {sink} sink can't be backfilled, since chunks are written out of order and can be written again. Use one of {}",
+            BACKFILL_SINKS.join(", ")
+        );
+    }
+    aurora_indexer::backfill::backfill(config, move || {
+        new_indexer(filter.clone(), metrics.clone(), health.clone())
+    })
+    .await
+    .expect("Backfill failed");
+}
+
+/// Checks the Redis streams for missing or duplicated events, and appends the
+/// missing ones with `--repair`
+async fn verify(args: &mut Vec<String>) {
+    let repair = args.iter().any(|arg| arg == "--repair");
+    args.retain(|arg| arg != "--repair");
+    let msg = "Usage: `indexer verify start-block end-block [--repair]`";
+    let (start, end) = (
+        block_height(args.first(), msg),
+        block_height(args.get(1), msg),
+    );
+
+    let mut verifier = StreamVerifier::new(redis_connection().await, 10_000)
+        .with_transaction_format(event_format("REDIS_TRANSACTION_FORMAT"))
+        .with_log_format(event_format("REDIS_LOG_FORMAT"))
+        .with_stream_prefix(std::env::var("VERIFY_STREAM_PREFIX").unwrap_or_default());
+    let reports = verifier
+        .verify(start, end)
+        .await
+        .expect("Verification failed");
+    for report in &reports {
+        if let (Some(first), Some(last)) = (report.trimmed.first(), report.trimmed.last()) {
+            tracing::warn!(
+                "{}: blocks {first}..={last} were trimmed from the stream, they aren't verified",
+                report.stream
+            );
+        }
+        for (height, events) in &report.missing {
+            tracing::warn!(
+                "{}: {} events missing in block {height}",
+                report.stream,
+                events.len()
+            );
+        }
+        for (height, events) in &report.duplicated {
+            tracing::warn!(
+                "{}: {} events duplicated in block {height}",
+                report.stream,
+                events.len()
+            );
+        }
+    }
+    if reports.iter().all(StreamReport::is_ok) {
+        tracing::info!("Blocks {start}..{end} are complete");
+    } else if repair {
+        let repaired = verifier.repair(&reports).await.expect("Repair failed");
+        tracing::info!(
+            "Added {repaired} missing events to the repair streams, duplicates are left as they are"
+        );
+    } else {
+        std::process::exit(1);
+    }
+}
+
+/// Block height argument, which may have `_`, `,`, `.` or spaces between digits
+fn block_height(arg: Option<&String>, usage: &str) -> BlockHeight {
+    arg.expect(usage)
+        .replace(['_', ',', ' ', '.'], "")
+        .parse()
+        .expect(usage)
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` on `address` in the background
+async fn serve_http(address: &str, metrics: Metrics, health: Health) {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to listen on {address}: {err}"));
+    tracing::info!("Serving metrics and health checks on {address}");
+    let router =
+        aurora_indexer::metrics::router(metrics).merge(aurora_indexer::health::router(health));
+    tokio::spawn(async move {
+        axum::serve(listener, router)
+            .await
+            .expect("HTTP server failed");
+    });
+}
+
+/// Stops the indexer after the current block on SIGINT or SIGTERM, and exits
+/// if that takes longer than `timeout`
+async fn handle_signals(shutdown: Shutdown, timeout: std::time::Duration) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.expect("Failed to listen for SIGINT"),
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl+C");
+
+    tracing::info!("Shutting down after the current block, waiting up to {timeout:?}");
+    shutdown.request();
+    tokio::time::sleep(timeout).await;
+    // The progress file is written after every block, so it's up to date
+    tracing::error!("Shutdown timed out, exiting without finishing the current block");
+    std::process::exit(1);
+}
+
+async fn sink(name: &str, metrics: &Metrics) -> Box<dyn AuroraEventHandler> {
+    match name {
+        "redis" => Box::new(redis_handler().await.with_metrics(metrics.clone())),
+        #[cfg(feature = "postgres")]
+        "postgres" => Box::new(
+            aurora_indexer::postgres_handler::PostgresHandler::connect(
+                &std::env::var("POSTGRES_URL").expect("No $POSTGRES_URL environment variable set"),
+            )
+            .await
+            .expect("Failed to connect to Postgres"),
+        ),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Box::new(
+            aurora_indexer::sqlite_handler::SqliteHandler::open(
+                std::env::var("SQLITE_PATH").expect("No $SQLITE_PATH environment variable set"),
+            )
+            .expect("Failed to open SQLite database"),
+        ),
+        #[cfg(feature = "clickhouse")]
+        "clickhouse" => {
+            use aurora_indexer::clickhouse_handler::{ClickHouseConfig, ClickHouseHandler};
+
+            let mut config = ClickHouseConfig::new(
+                std::env::var("CLICKHOUSE_URL")
+                    .expect("No $CLICKHOUSE_URL environment variable set"),
+            );
+            if let Ok(database) = std::env::var("CLICKHOUSE_DATABASE") {
+                config.database = database;
+            }
+            config.user = std::env::var("CLICKHOUSE_USER").ok();
+            config.password = std::env::var("CLICKHOUSE_PASSWORD").ok();
+            Box::new(
+                ClickHouseHandler::new(config)
+                    .await
+                    .expect("Failed to connect to ClickHouse"),
+            )
+        }
+        #[cfg(feature = "kafka")]
+        "kafka" => {
+            use aurora_indexer::kafka_handler::{KafkaConfig, KafkaHandler};
+
+            let mut config = KafkaConfig::new(
+                std::env::var("KAFKA_BROKERS").expect("No $KAFKA_BROKERS environment variable set"),
+            );
+            if let Ok(transactional_id) = std::env::var("KAFKA_TRANSACTIONAL_ID") {
+                config.transactional_id = transactional_id;
+            }
+            if let Ok(topic) = std::env::var("KAFKA_TRANSACTIONS_TOPIC") {
+                config.topics.transactions = topic;
+            }
+            if let Ok(topic) = std::env::var("KAFKA_LOGS_TOPIC") {
+                config.topics.logs = topic;
+            }
+            if let Ok(topic) = std::env::var("KAFKA_CHECKPOINT_TOPIC") {
+                config.checkpoint_topic = topic;
+            }
+            if let Ok(engine_topics) = std::env::var("KAFKA_ENGINE_TOPICS") {
+                config.engine_topics =
+                    serde_json::from_str(&engine_topics).expect("Invalid $KAFKA_ENGINE_TOPICS");
+            }
+            config.format = event_format("KAFKA_FORMAT");
+            Box::new(
+                KafkaHandler::new(config)
+                    .await
+                    .expect("Failed to create Kafka producer"),
+            )
+        }
+        #[cfg(feature = "nats")]
+        "nats" => {
+            use aurora_indexer::nats_handler::{NatsConfig, NatsHandler};
+
+            let mut config = NatsConfig::new(
+                std::env::var("NATS_URL").expect("No $NATS_URL environment variable set"),
+            );
+            if let Ok(subject_prefix) = std::env::var("NATS_SUBJECT_PREFIX") {
+                config.subject_prefix = subject_prefix;
+            }
+            config.stream_name = std::env::var("NATS_STREAM").ok();
+            config.format = event_format("NATS_FORMAT");
+            Box::new(
+                NatsHandler::new(config)
+                    .await
+                    .expect("Failed to connect to NATS"),
+            )
+        }
+        #[cfg(feature = "webhook")]
+        "webhook" => {
+            use aurora_indexer::webhook_handler::{WebhookConfig, WebhookHandler};
+
+            let path = std::env::var("WEBHOOK_SUBSCRIBERS")
+                .expect("No $WEBHOOK_SUBSCRIBERS environment variable set");
+            let subscribers = serde_json::from_str(
+                &std::fs::read_to_string(&path)
+                    .unwrap_or_else(|err| panic!("Failed to read {path}: {err}")),
+            )
+            .unwrap_or_else(|err| panic!("Invalid subscribers in {path}: {err}"));
+            let mut config = WebhookConfig::new(
+                subscribers,
+                std::env::var("WEBHOOK_QUEUE").unwrap_or_else(|_| "webhook-queue.json".to_owned()),
+            );
+            if let Ok(concurrency) = std::env::var("WEBHOOK_CONCURRENCY") {
+                config.concurrency = concurrency.parse().expect("Invalid $WEBHOOK_CONCURRENCY");
+            }
+            Box::new(WebhookHandler::new(config).expect("Failed to load webhook retry queue"))
+        }
+        #[cfg(feature = "jsonl")]
+        "jsonl" => {
+            use aurora_indexer::jsonl_handler::{JsonlConfig, JsonlHandler};
+
+            let mut config = JsonlConfig::new(archive_directory());
+            config.rotation = archive_rotation();
+            config.zstd_level = std::env::var("JSONL_ZSTD_LEVEL")
+                .ok()
+                .map(|level| level.parse().expect("Invalid $JSONL_ZSTD_LEVEL"));
+            if std::env::var("JSONL_FORMAT").is_ok() {
+                config.format = event_format("JSONL_FORMAT");
+            }
+            Box::new(JsonlHandler::new(config).expect("Failed to open archive directory"))
+        }
+        #[cfg(feature = "parquet")]
+        "parquet" => {
+            use aurora_indexer::parquet_handler::{ParquetConfig, ParquetHandler};
+
+            let mut config = ParquetConfig::new(archive_directory());
+            config.rotation = archive_rotation();
+            Box::new(ParquetHandler::new(config).expect("Failed to open archive directory"))
+        }
+        #[cfg(feature = "scripting")]
+        "script" => {
+            use aurora_indexer::script_handler::{Script, ScriptHandler, ScriptLimits};
+
+            let path =
+                std::env::var("SCRIPT_PATH").expect("No $SCRIPT_PATH environment variable set");
+            let mut limits = ScriptLimits::default();
+            if let Ok(max_operations) = std::env::var("SCRIPT_MAX_OPERATIONS") {
+                limits.max_operations = max_operations
+                    .parse()
+                    .expect("Invalid $SCRIPT_MAX_OPERATIONS");
+            }
+            let script = Script::load(&path, limits)
+                .unwrap_or_else(|err| panic!("Failed to load script {path}: {err}"));
+            let mut handler = ScriptHandler::new(script, redis_connection().await, 10_000)
+                .with_metrics(metrics.clone());
+            if let Ok(prefix) = std::env::var("SCRIPT_STREAM_PREFIX") {
+                handler = handler.with_stream_prefix(prefix);
+            }
+            if let Ok(key) = std::env::var("SCRIPT_CHECKPOINT_KEY") {
+                handler = handler.with_checkpoint_key(key);
+            }
+            Box::new(handler)
+        }
+        #[cfg(feature = "websocket")]
+        "websocket" => {
+            use aurora_indexer::websocket_handler::{
+                router, Subscriptions, WebSocketHandler, DEFAULT_BUFFER_BLOCKS,
+            };
+
+            let subscriptions = Subscriptions::new(
+                std::env::var("WS_BUFFER_BLOCKS")
+                    .map(|blocks| blocks.parse().expect("Invalid $WS_BUFFER_BLOCKS"))
+                    .unwrap_or(DEFAULT_BUFFER_BLOCKS),
+            );
+            let address = std::env::var("WS_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8546".to_owned());
+            let listener = tokio::net::TcpListener::bind(&address)
+                .await
+                .unwrap_or_else(|err| panic!("Failed to listen on {address}: {err}"));
+            tracing::info!("Serving WebSocket subscriptions on {address}");
+            let router = router(subscriptions.clone());
+            tokio::spawn(async move {
+                axum::serve(listener, router)
+                    .await
+                    .expect("WebSocket server failed");
+            });
+            let mut handler = WebSocketHandler::new(subscriptions);
+            if let Ok(engine_account_id) = std::env::var("WS_ENGINE_ACCOUNT_ID") {
+                handler = handler.with_engine_account_id(
+                    engine_account_id
+                        .parse()
+                        .expect("Invalid $WS_ENGINE_ACCOUNT_ID"),
+                );
+            }
+            Box::new(handler)
+        }
+        other => panic!("Unknown or disabled sink {other}"),
+    }
+}
+
+/// `<SINK>_POLICY`, when several sinks are used
+fn failure_policy(sink: &str) -> FailurePolicy {
+    let variable = format!("{}_POLICY", sink.to_uppercase());
+    match std::env::var(&variable).as_deref() {
+        Err(_) | Ok("required") => FailurePolicy::Required,
+        Ok("best_effort") => FailurePolicy::BestEffort,
+        Ok("buffered") => FailurePolicy::Buffered {
+            max_blocks: std::env::var(format!("{}_MAX_BUFFERED_BLOCKS", sink.to_uppercase()))
+                .map(|max_blocks| max_blocks.parse().expect("Invalid maximum buffered blocks"))
+                .unwrap_or(1000),
         },
+        Ok(other) => {
+            panic!("Invalid ${variable} {other}, expected `required`, `best_effort` or `buffered`")
+        }
+    }
+}
+
+async fn redis_connection() -> ConnectionManager {
+    let client = redis::Client::open(
+        std::env::var("REDIS_URL").expect("No $REDIS_URL environment variable set"),
     )
-    .await
-    .expect("Indexer run failed");
+    .unwrap();
+    ConnectionManager::new(client).await.unwrap()
+}
+
+async fn redis_handler() -> PushToRedisStream {
+    PushToRedisStream::new(redis_connection().await, 10_000)
+        .await
+        .with_transaction_format(event_format("REDIS_TRANSACTION_FORMAT"))
+        .with_log_format(event_format("REDIS_LOG_FORMAT"))
+}
+
+/// Removes `name <value>` from the arguments and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == name)?;
+    let value = args
+        .get(position + 1)
+        .unwrap_or_else(|| panic!("{name} requires a value"))
+        .clone();
+    args.drain(position..=position + 1);
+    Some(value)
+}
+
+fn event_format(variable: &str) -> EventFormat {
+    std::env::var(variable)
+        .map(|format| {
+            format
+                .parse()
+                .unwrap_or_else(|err| panic!("Invalid ${variable}: {err}"))
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(any(feature = "jsonl", feature = "parquet"))]
+fn archive_directory() -> String {
+    std::env::var("ARCHIVE_DIRECTORY").expect("No $ARCHIVE_DIRECTORY environment variable set")
+}
+
+#[cfg(any(feature = "jsonl", feature = "parquet"))]
+fn archive_rotation() -> aurora_indexer::archive::Rotation {
+    let mut rotation = aurora_indexer::archive::Rotation::default();
+    if let Ok(max_blocks) = std::env::var("ARCHIVE_MAX_BLOCKS") {
+        rotation.max_blocks = Some(max_blocks.parse().expect("Invalid $ARCHIVE_MAX_BLOCKS"));
+    }
+    if let Ok(max_bytes) = std::env::var("ARCHIVE_MAX_BYTES") {
+        rotation.max_bytes = Some(max_bytes.parse().expect("Invalid $ARCHIVE_MAX_BYTES"));
+    }
+    rotation
 }
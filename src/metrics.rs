@@ -0,0 +1,136 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+/// Prometheus metrics of an indexer and its sinks.
+///
+/// Clones share the same metrics, so one instance can be passed to
+/// [`AuroraIndexer::with_metrics`](crate::AuroraIndexer::with_metrics) and to
+/// the sinks, and served with [`router`].
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Last flushed block
+    pub(crate) block_height: IntGauge,
+    /// Seconds between the last flushed block's timestamp and its flush
+    pub(crate) lag_seconds: Gauge,
+    /// Events passed to the handler, by `kind` and `engine_account_id`
+    pub(crate) events: IntCounterVec,
+    /// Submits that couldn't be decoded, by `stage`
+    pub(crate) decode_failures: IntCounterVec,
+    /// Time to flush a block through all handlers
+    pub(crate) flush_duration: Histogram,
+    /// Time for a sink to write a block, by `sink`
+    pub(crate) sink_write_duration: HistogramVec,
+    /// Failed sink writes, including ones that were retried, by `sink`
+    pub(crate) sink_errors: IntCounterVec,
+    /// Events a script failed on, by `kind`
+    pub(crate) script_dropped_events: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::new(),
+            block_height: IntGauge::new(
+                "aurora_indexer_block_height",
+                "Height of the last flushed block",
+            )
+            .unwrap(),
+            lag_seconds: Gauge::new(
+                "aurora_indexer_lag_seconds",
+                "Seconds between the timestamp of the last flushed block and its flush",
+            )
+            .unwrap(),
+            events: IntCounterVec::new(
+                Opts::new("aurora_indexer_events_total", "Events emitted"),
+                &["kind", "engine_account_id"],
+            )
+            .unwrap(),
+            decode_failures: IntCounterVec::new(
+                Opts::new(
+                    "aurora_indexer_decode_failures_total",
+                    "Submits that couldn't be decoded, by the stage that failed",
+                ),
+                &["stage"],
+            )
+            .unwrap(),
+            flush_duration: Histogram::with_opts(HistogramOpts::new(
+                "aurora_indexer_flush_duration_seconds",
+                "Time to flush a block through all handlers",
+            ))
+            .unwrap(),
+            sink_write_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "aurora_indexer_sink_write_duration_seconds",
+                    "Time for a sink to write a block",
+                ),
+                &["sink"],
+            )
+            .unwrap(),
+            sink_errors: IntCounterVec::new(
+                Opts::new(
+                    "aurora_indexer_sink_errors_total",
+                    "Failed sink writes, including retried ones",
+                ),
+                &["sink"],
+            )
+            .unwrap(),
+            script_dropped_events: IntCounterVec::new(
+                Opts::new(
+                    "aurora_indexer_script_dropped_events_total",
+                    "Events dropped because the script failed on them",
+                ),
+                &["kind"],
+            )
+            .unwrap(),
+        };
+        for collector in [
+            Box::new(metrics.block_height.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.lag_seconds.clone()),
+            Box::new(metrics.events.clone()),
+            Box::new(metrics.decode_failures.clone()),
+            Box::new(metrics.flush_duration.clone()),
+            Box::new(metrics.sink_write_duration.clone()),
+            Box::new(metrics.sink_errors.clone()),
+            Box::new(metrics.script_dropped_events.clone()),
+        ] {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("Failed to encode metrics")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `GET /metrics`
+pub fn router(metrics: Metrics) -> Router {
+    Router::new().route(
+        "/metrics",
+        get(move || async move {
+            (
+                [(CONTENT_TYPE, TextEncoder::new().format_type().to_owned())],
+                metrics.encode(),
+            )
+        }),
+    )
+}
@@ -0,0 +1,170 @@
+use async_nats::header::NATS_MESSAGE_ID;
+use async_nats::jetstream::{self, stream};
+use async_nats::HeaderMap;
+use async_trait::async_trait;
+use aurora_engine_types::types::Address;
+use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+
+use crate::format::{EthereumLogEvent, EthereumTransactionEvent, EventFormat};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+pub struct NatsConfig {
+    pub url: String,
+    /// Subject prefix, events are published to `<prefix>.<engine>.tx` and
+    /// `<prefix>.<engine>.log.<address>`
+    pub subject_prefix: String,
+    /// If set, a stream with this name capturing all subjects under the prefix
+    /// is created if it doesn't exist yet
+    pub stream_name: Option<String>,
+    pub format: EventFormat,
+}
+
+impl NatsConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            subject_prefix: "aurora".to_owned(),
+            stream_name: None,
+            format: EventFormat::default(),
+        }
+    }
+}
+
+/// Subject of the transactions of an engine account. Dots separate subject
+/// tokens and silo accounts usually have them, so they're replaced with `_`.
+pub fn transaction_subject(prefix: &str, engine_account_id: &AccountId) -> String {
+    format!("{}.tx", engine_subject(prefix, engine_account_id))
+}
+
+/// Subject of the logs a contract of an engine account emits
+pub fn log_subject(prefix: &str, engine_account_id: &AccountId, address: &Address) -> String {
+    format!(
+        "{}.log.0x{}",
+        engine_subject(prefix, engine_account_id),
+        hex::encode(address.raw().as_bytes())
+    )
+}
+
+fn engine_subject(prefix: &str, engine_account_id: &AccountId) -> String {
+    format!("{prefix}.{}", engine_account_id.as_str().replace('.', "_"))
+}
+
+/// Publishes events to NATS JetStream.
+///
+/// Messages carry a `Nats-Msg-Id` derived from the receipt id, transaction hash
+/// and log index, so the server drops duplicates of replayed blocks within the
+/// stream's duplicate window. A block is flushed only after the server
+/// acknowledged all of its messages, and is published again if it wasn't.
+pub struct NatsHandler {
+    jetstream: jetstream::Context,
+    config: NatsConfig,
+    messages: Vec<Message>,
+}
+
+struct Message {
+    subject: String,
+    id: String,
+    payload: String,
+}
+
+impl NatsHandler {
+    pub async fn new(config: NatsConfig) -> Result<Self, async_nats::Error> {
+        let client = async_nats::connect(config.url.as_str()).await?;
+        let jetstream = jetstream::new(client);
+        if let Some(stream_name) = &config.stream_name {
+            jetstream
+                .get_or_create_stream(stream::Config {
+                    name: stream_name.clone(),
+                    subjects: vec![format!("{}.>", config.subject_prefix)],
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(Self {
+            jetstream,
+            config,
+            messages: Vec::new(),
+        })
+    }
+
+    async fn publish_block(&mut self) -> Result<(), HandlerError> {
+        let mut acks = Vec::with_capacity(self.messages.len());
+        for message in &self.messages {
+            let mut headers = HeaderMap::new();
+            headers.insert(NATS_MESSAGE_ID, message.id.as_str());
+            acks.push(
+                self.jetstream
+                    .publish_with_headers(
+                        message.subject.clone(),
+                        headers,
+                        message.payload.clone().into(),
+                    )
+                    .await?,
+            );
+        }
+        for ack in acks {
+            ack.await?;
+        }
+        self.messages.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for NatsHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let subject = transaction_subject(&self.config.subject_prefix, &details.engine_account_id);
+        let id = format!("{}:{:#x}", event.receipt_id, details.tx_hash);
+        let payload = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => {
+                serde_json::to_string(&EthereumTransactionEvent::new(&event, &details))
+            }
+        }?;
+        self.messages.push(Message {
+            subject,
+            id,
+            payload,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let subject = log_subject(
+            &self.config.subject_prefix,
+            &event.engine_account_id,
+            &event.address,
+        );
+        let id = format!(
+            "{}:{}:{}",
+            event.receipt_id, event.aurora_tx_hash, event.log_index
+        );
+        let payload = match self.config.format {
+            EventFormat::Legacy => serde_json::to_string(&event),
+            EventFormat::Ethereum => serde_json::to_string(&EthereumLogEvent::new(&event)),
+        }?;
+        self.messages.push(Message {
+            subject,
+            id,
+            payload,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, _block_height: BlockHeight) -> Result<(), HandlerError> {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+        self.publish_block().await
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.messages.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BinaryArray, FixedSizeBinaryArray, RecordBatch, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::archive::{partial_path, Kind, Manifest, Rotation, Segment};
+use crate::format::{status_name, status_output};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+pub struct ParquetConfig {
+    pub directory: PathBuf,
+    pub rotation: Rotation,
+    pub compression: Compression,
+}
+
+impl ParquetConfig {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            rotation: Rotation::default(),
+            compression: Compression::ZSTD(Default::default()),
+        }
+    }
+}
+
+fn transactions_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("block_height", DataType::UInt64, false),
+        Field::new("block_timestamp_nanosec", DataType::UInt64, false),
+        Field::new("transaction_id", DataType::Utf8, false),
+        Field::new("receipt_id", DataType::Utf8, false),
+        Field::new("index", DataType::UInt32, false),
+        Field::new("engine_account_id", DataType::Utf8, false),
+        Field::new("tx_hash", DataType::FixedSizeBinary(32), false),
+        Field::new("chain_id", DataType::UInt64, true),
+        Field::new("from_address", DataType::FixedSizeBinary(20), false),
+        Field::new("to_address", DataType::FixedSizeBinary(20), true),
+        // Decimal amount of wei, u256 doesn't fit into Decimal256
+        Field::new("value", DataType::Utf8, false),
+        Field::new("input", DataType::Binary, false),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("output", DataType::Binary, true),
+        Field::new("gas_used", DataType::UInt64, false),
+        Field::new("signature", DataType::Utf8, true),
+    ]))
+}
+
+fn logs_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("block_height", DataType::UInt64, false),
+        Field::new("block_timestamp_nanosec", DataType::UInt64, false),
+        Field::new("transaction_id", DataType::Utf8, false),
+        Field::new("receipt_id", DataType::Utf8, false),
+        Field::new("index", DataType::UInt32, false),
+        Field::new("log_index", DataType::UInt32, false),
+        Field::new("engine_account_id", DataType::Utf8, false),
+        Field::new("tx_hash", DataType::FixedSizeBinary(32), false),
+        Field::new("address", DataType::FixedSizeBinary(20), false),
+        Field::new("topic0", DataType::FixedSizeBinary(32), true),
+        Field::new("topic1", DataType::FixedSizeBinary(32), true),
+        Field::new("topic2", DataType::FixedSizeBinary(32), true),
+        Field::new("topic3", DataType::FixedSizeBinary(32), true),
+        Field::new("data", DataType::Binary, false),
+        Field::new("signature", DataType::Utf8, true),
+    ]))
+}
+
+struct TransactionRow {
+    block_height: BlockHeight,
+    block_timestamp_nanosec: u64,
+    transaction_id: String,
+    receipt_id: String,
+    index: u32,
+    engine_account_id: String,
+    tx_hash: [u8; 32],
+    chain_id: Option<u64>,
+    from_address: [u8; 20],
+    to_address: Option<[u8; 20]>,
+    value: String,
+    input: Vec<u8>,
+    status: &'static str,
+    output: Option<Vec<u8>>,
+    gas_used: u64,
+    signature: Option<String>,
+}
+
+struct LogRow {
+    block_height: BlockHeight,
+    block_timestamp_nanosec: u64,
+    transaction_id: String,
+    receipt_id: String,
+    index: u32,
+    log_index: u32,
+    engine_account_id: String,
+    tx_hash: [u8; 32],
+    address: [u8; 20],
+    topics: Vec<[u8; 32]>,
+    data: Vec<u8>,
+    signature: Option<String>,
+}
+
+fn fixed_size_binary<const N: usize>(
+    values: impl IntoIterator<Item = Option<[u8; N]>>,
+) -> ArrayRef {
+    Arc::new(
+        FixedSizeBinaryArray::try_from_sparse_iter_with_size(values.into_iter(), N as i32)
+            .expect("Values have a fixed size"),
+    )
+}
+
+fn transactions_batch(rows: &[TransactionRow]) -> RecordBatch {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.block_height),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.block_timestamp_nanosec),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.transaction_id),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.receipt_id),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.index),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.engine_account_id),
+        )),
+        fixed_size_binary(rows.iter().map(|row| Some(row.tx_hash))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|row| row.chain_id))),
+        fixed_size_binary(rows.iter().map(|row| Some(row.from_address))),
+        fixed_size_binary(rows.iter().map(|row| row.to_address)),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.value),
+        )),
+        Arc::new(BinaryArray::from_iter_values(
+            rows.iter().map(|row| &row.input),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.status),
+        )),
+        Arc::new(BinaryArray::from_iter(
+            rows.iter().map(|row| row.output.as_ref()),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.gas_used),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|row| row.signature.as_ref()),
+        )),
+    ];
+    RecordBatch::try_new(transactions_schema(), columns).expect("Columns match the schema")
+}
+
+fn logs_batch(rows: &[LogRow]) -> RecordBatch {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.block_height),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.block_timestamp_nanosec),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.transaction_id),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.receipt_id),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.index),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.log_index),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.engine_account_id),
+        )),
+        fixed_size_binary(rows.iter().map(|row| Some(row.tx_hash))),
+        fixed_size_binary(rows.iter().map(|row| Some(row.address))),
+    ];
+    for topic in 0..4 {
+        columns.push(fixed_size_binary(
+            rows.iter().map(|row| row.topics.get(topic).copied()),
+        ));
+    }
+    columns.push(Arc::new(BinaryArray::from_iter_values(
+        rows.iter().map(|row| &row.data),
+    )));
+    columns.push(Arc::new(StringArray::from_iter(
+        rows.iter().map(|row| row.signature.as_ref()),
+    )));
+    RecordBatch::try_new(logs_schema(), columns).expect("Columns match the schema")
+}
+
+fn close(writer: ArrowWriter<File>) -> io::Result<()> {
+    writer.into_inner().map_err(io::Error::other)?.sync_all()
+}
+
+/// Writes events to rotating Parquet files with a typed schema, one file
+/// series for transactions and one for logs.
+///
+/// Files are named `<kind>-<first block>-<last block>.parquet` and listed in
+/// `manifest.json` once finalized. Hashes and addresses are stored as fixed
+/// size binary, log topics are split into `topic0`..`topic3` columns.
+///
+/// A Parquet file can only be read once it's closed, so indexing resumes
+/// after the last block of the finalized files, see [`Manifest::checkpoint`],
+/// and unfinished files are written again. Blocks that are already in
+/// finalized files aren't written again.
+pub struct ParquetHandler {
+    config: ParquetConfig,
+    manifest: Manifest,
+    transactions: Vec<TransactionRow>,
+    logs: Vec<LogRow>,
+    transactions_file: Option<Segment<ArrowWriter<File>>>,
+    logs_file: Option<Segment<ArrowWriter<File>>>,
+}
+
+impl ParquetHandler {
+    pub fn new(config: ParquetConfig) -> io::Result<Self> {
+        Ok(Self {
+            manifest: Manifest::open(&config.directory)?,
+            config,
+            transactions: Vec::new(),
+            logs: Vec::new(),
+            transactions_file: None,
+            logs_file: None,
+        })
+    }
+
+    fn write_block(&mut self, kind: Kind, block_height: BlockHeight) -> io::Result<()> {
+        if self
+            .manifest
+            .last_block(kind)
+            .is_some_and(|last_block| block_height <= last_block)
+        {
+            return Ok(());
+        }
+        let (batch, schema, segment) = match kind {
+            Kind::Transactions => (
+                transactions_batch(&self.transactions),
+                transactions_schema(),
+                &mut self.transactions_file,
+            ),
+            Kind::Logs => (logs_batch(&self.logs), logs_schema(), &mut self.logs_file),
+        };
+        if segment.is_none() {
+            let path = partial_path(&self.manifest, kind, "parquet", block_height);
+            let properties = WriterProperties::builder()
+                .set_compression(self.config.compression)
+                .build();
+            let writer = ArrowWriter::try_new(File::create(path)?, schema, Some(properties))
+                .map_err(io::Error::other)?;
+            *segment = Some(Segment::new(writer, kind, "parquet", block_height));
+        }
+        let file = segment.as_mut().unwrap();
+        file.writer.write(&batch).map_err(io::Error::other)?;
+        file.events += batch.num_rows() as u64;
+        file.bytes = (file.writer.bytes_written() + file.writer.in_progress_size()) as u64;
+        file.end_block(block_height);
+        if file.should_rotate(&self.config.rotation) {
+            segment
+                .take()
+                .unwrap()
+                .finalize(&mut self.manifest, close)?;
+        }
+        Ok(())
+    }
+
+    fn finalize_files(&mut self) -> io::Result<()> {
+        for segment in [self.transactions_file.take(), self.logs_file.take()]
+            .into_iter()
+            .flatten()
+        {
+            segment.finalize(&mut self.manifest, close)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for ParquetHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let receipt_id = event.receipt_id.to_string();
+        let index = self
+            .transactions
+            .iter()
+            .filter(|row| row.receipt_id == receipt_id)
+            .count() as u32;
+        self.transactions.push(TransactionRow {
+            block_height: event.block_height,
+            block_timestamp_nanosec: event.block_timestamp_nanosec as u64,
+            transaction_id: event.transaction_id.to_string(),
+            receipt_id,
+            index,
+            engine_account_id: details.engine_account_id.to_string(),
+            tx_hash: details.tx_hash.0,
+            chain_id: event.chain_id,
+            from_address: event.from.raw().0,
+            to_address: event.to.map(|to| to.raw().0),
+            value: event.value.raw().to_string(),
+            status: status_name(&event.status),
+            output: status_output(&event.status).map(|output| output.to_vec()),
+            input: event.input,
+            gas_used: details.gas_used,
+            signature: details.signature,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let receipt_id = event.receipt_id.to_string();
+        let (index, tx_hash) = self
+            .transactions
+            .iter()
+            .rev()
+            .find(|row| row.receipt_id == receipt_id)
+            .map_or((0, [0; 32]), |row| (row.index, row.tx_hash));
+        self.logs.push(LogRow {
+            block_height: event.block_height,
+            block_timestamp_nanosec: event.block_timestamp_nanosec as u64,
+            transaction_id: event.transaction_id.to_string(),
+            receipt_id,
+            index,
+            log_index: event.log_index as u32,
+            engine_account_id: event.engine_account_id.to_string(),
+            tx_hash,
+            address: event.address.raw().0,
+            topics: event.topics,
+            data: event.data,
+            signature: event.signature,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        // Cleared only once written, so that a failed block can be written again
+        self.write_block(Kind::Transactions, block_height)?;
+        self.transactions.clear();
+        self.write_block(Kind::Logs, block_height)?;
+        self.logs.clear();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        self.finalize_files()?;
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        Ok(self.manifest.checkpoint())
+    }
+}
@@ -0,0 +1,405 @@
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::{AuroraTransactionEvent, TransactionStatus};
+use tokio_postgres::{Client, NoTls};
+
+use crate::format::{deployed_contract, revert_reason, status_name, status_output};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+/// Channel notified with the height of every written block, for readers that
+/// follow the indexer, like the GraphQL subscriptions
+pub const BLOCKS_CHANNEL: &str = "aurora_blocks";
+
+/// Schema migrations, applied in order. Never edit an existing one, add a new
+/// migration instead.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE aurora_transactions (
+    block_height BIGINT NOT NULL,
+    receipt_id TEXT NOT NULL,
+    index INTEGER NOT NULL,
+    block_timestamp_nanosec BIGINT NOT NULL,
+    transaction_id TEXT NOT NULL,
+    tx_hash BYTEA NOT NULL,
+    chain_id BIGINT,
+    from_address BYTEA NOT NULL,
+    to_address BYTEA,
+    value NUMERIC(78, 0) NOT NULL,
+    input BYTEA NOT NULL,
+    status TEXT NOT NULL,
+    output BYTEA,
+    gas_used BIGINT NOT NULL,
+    signature TEXT,
+    PRIMARY KEY (block_height, receipt_id, index)
+);
+CREATE INDEX aurora_transactions_tx_hash ON aurora_transactions (tx_hash);
+CREATE INDEX aurora_transactions_from_address ON aurora_transactions (from_address, block_height);
+CREATE INDEX aurora_transactions_to_address ON aurora_transactions (to_address, block_height);
+
+CREATE TABLE aurora_logs (
+    block_height BIGINT NOT NULL,
+    receipt_id TEXT NOT NULL,
+    index INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    block_timestamp_nanosec BIGINT NOT NULL,
+    tx_hash BYTEA NOT NULL,
+    address BYTEA NOT NULL,
+    topic0 BYTEA,
+    topics BYTEA[] NOT NULL,
+    data BYTEA NOT NULL,
+    signature TEXT,
+    PRIMARY KEY (block_height, receipt_id, index, log_index)
+);
+CREATE INDEX aurora_logs_address ON aurora_logs (address, block_height);
+CREATE INDEX aurora_logs_topic0 ON aurora_logs (topic0, block_height);
+
+CREATE TABLE aurora_deployments (
+    block_height BIGINT NOT NULL,
+    receipt_id TEXT NOT NULL,
+    index INTEGER NOT NULL,
+    block_timestamp_nanosec BIGINT NOT NULL,
+    tx_hash BYTEA NOT NULL,
+    deployer BYTEA NOT NULL,
+    contract_address BYTEA NOT NULL,
+    PRIMARY KEY (block_height, receipt_id, index)
+);
+CREATE INDEX aurora_deployments_contract_address ON aurora_deployments (contract_address);
+
+CREATE TABLE aurora_failures (
+    block_height BIGINT NOT NULL,
+    receipt_id TEXT NOT NULL,
+    index INTEGER NOT NULL,
+    block_timestamp_nanosec BIGINT NOT NULL,
+    tx_hash BYTEA NOT NULL,
+    status TEXT NOT NULL,
+    output BYTEA,
+    revert_reason TEXT,
+    PRIMARY KEY (block_height, receipt_id, index)
+);
+"#,
+    r#"
+CREATE TABLE aurora_checkpoint (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    last_flushed_height BIGINT NOT NULL
+);
+"#,
+    // Positions in the Aurora block, NULL for rows written before until their
+    // blocks are indexed again
+    r#"
+ALTER TABLE aurora_transactions
+    ADD COLUMN transaction_index INTEGER,
+    ADD COLUMN cumulative_gas_used BIGINT;
+ALTER TABLE aurora_logs ADD COLUMN block_log_index INTEGER;
+"#,
+];
+
+struct TransactionRow {
+    event: AuroraTransactionEvent,
+    details: TransactionDetails,
+    /// Position of the transaction within its receipt
+    index: i32,
+}
+
+struct LogRow {
+    event: AuroraLogEvent,
+    /// Position of the log's transaction within its receipt
+    index: i32,
+    /// Decoded `aurora_tx_hash`
+    tx_hash: Vec<u8>,
+    /// Position of the log in the Aurora block
+    block_log_index: i32,
+}
+
+/// Stores events in PostgreSQL, one database transaction per block.
+///
+/// Rows are keyed by `(block_height, receipt_id, index)`, so indexing the same
+/// blocks again doesn't create duplicates. The height of the last written
+/// block is stored in `aurora_checkpoint` in the same transaction, and
+/// [`BLOCKS_CHANNEL`] is notified with it when the transaction commits.
+pub struct PostgresHandler {
+    client: Client,
+    transactions: Vec<TransactionRow>,
+    logs: Vec<LogRow>,
+}
+
+impl PostgresHandler {
+    pub async fn connect(url: &str) -> Result<Self, tokio_postgres::Error> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::error!("Postgres connection error: {err}");
+            }
+        });
+        Self::new(client).await
+    }
+
+    pub async fn new(mut client: Client) -> Result<Self, tokio_postgres::Error> {
+        migrate(&mut client).await?;
+        Ok(Self {
+            client,
+            transactions: Vec::new(),
+            logs: Vec::new(),
+        })
+    }
+
+    async fn write_block(
+        &mut self,
+        block_height: BlockHeight,
+    ) -> Result<(), tokio_postgres::Error> {
+        let transaction = self.client.transaction().await?;
+        let insert_transaction = transaction
+            .prepare(
+                "INSERT INTO aurora_transactions (block_height, receipt_id, index, block_timestamp_nanosec, transaction_id, tx_hash, chain_id, from_address, to_address, value, input, status, output, gas_used, signature, transaction_index, cumulative_gas_used)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10::TEXT::NUMERIC, $11, $12, $13, $14, $15, $16, $17)
+                ON CONFLICT (block_height, receipt_id, index) DO UPDATE SET transaction_index = EXCLUDED.transaction_index, cumulative_gas_used = EXCLUDED.cumulative_gas_used",
+            )
+            .await?;
+        let insert_deployment = transaction
+            .prepare(
+                "INSERT INTO aurora_deployments (block_height, receipt_id, index, block_timestamp_nanosec, tx_hash, deployer, contract_address)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT DO NOTHING",
+            )
+            .await?;
+        let insert_failure = transaction
+            .prepare(
+                "INSERT INTO aurora_failures (block_height, receipt_id, index, block_timestamp_nanosec, tx_hash, status, output, revert_reason)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT DO NOTHING",
+            )
+            .await?;
+        let insert_log = transaction
+            .prepare(
+                "INSERT INTO aurora_logs (block_height, receipt_id, index, log_index, block_timestamp_nanosec, tx_hash, address, topic0, topics, data, signature, block_log_index)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                ON CONFLICT (block_height, receipt_id, index, log_index) DO UPDATE SET block_log_index = EXCLUDED.block_log_index",
+            )
+            .await?;
+
+        for TransactionRow {
+            event,
+            details,
+            index,
+        } in &self.transactions
+        {
+            let block_height = event.block_height as i64;
+            let receipt_id = event.receipt_id.to_string();
+            let block_timestamp_nanosec = event.block_timestamp_nanosec as i64;
+            let tx_hash = details.tx_hash.as_bytes();
+            let output = status_output(&event.status);
+            transaction
+                .execute(
+                    &insert_transaction,
+                    &[
+                        &block_height,
+                        &receipt_id,
+                        &index,
+                        &block_timestamp_nanosec,
+                        &event.transaction_id.to_string(),
+                        &tx_hash,
+                        &event.chain_id.map(|chain_id| chain_id as i64),
+                        &event.from.raw().as_bytes(),
+                        &event.to.as_ref().map(|to| to.raw().as_bytes().to_vec()),
+                        &event.value.raw().to_string(),
+                        &event.input,
+                        &status_name(&event.status),
+                        &output,
+                        &(details.gas_used as i64),
+                        &details.signature,
+                        &(details.transaction_index as i32),
+                        &(details.cumulative_gas_used as i64),
+                    ],
+                )
+                .await?;
+            if let Some(contract_address) = deployed_contract(&event) {
+                transaction
+                    .execute(
+                        &insert_deployment,
+                        &[
+                            &block_height,
+                            &receipt_id,
+                            &index,
+                            &block_timestamp_nanosec,
+                            &tx_hash,
+                            &event.from.raw().as_bytes(),
+                            &contract_address.raw().as_bytes(),
+                        ],
+                    )
+                    .await?;
+            }
+            if !matches!(event.status, TransactionStatus::Succeed(_)) {
+                transaction
+                    .execute(
+                        &insert_failure,
+                        &[
+                            &block_height,
+                            &receipt_id,
+                            &index,
+                            &block_timestamp_nanosec,
+                            &tx_hash,
+                            &status_name(&event.status),
+                            &output,
+                            &output.and_then(revert_reason),
+                        ],
+                    )
+                    .await?;
+            }
+        }
+
+        for LogRow {
+            event,
+            index,
+            tx_hash,
+            block_log_index,
+        } in &self.logs
+        {
+            let topics = event
+                .topics
+                .iter()
+                .map(|topic| topic.to_vec())
+                .collect::<Vec<_>>();
+            transaction
+                .execute(
+                    &insert_log,
+                    &[
+                        &(event.block_height as i64),
+                        &event.receipt_id.to_string(),
+                        &index,
+                        &(event.log_index as i32),
+                        &(event.block_timestamp_nanosec as i64),
+                        &tx_hash,
+                        &event.address.raw().as_bytes(),
+                        &topics.first(),
+                        &topics,
+                        &event.data,
+                        &event.signature,
+                        &block_log_index,
+                    ],
+                )
+                .await?;
+        }
+
+        // Never moves back when older blocks are indexed again
+        transaction
+            .execute(
+                "INSERT INTO aurora_checkpoint (last_flushed_height) VALUES ($1)
+                ON CONFLICT (id) DO UPDATE SET last_flushed_height = GREATEST(aurora_checkpoint.last_flushed_height, EXCLUDED.last_flushed_height)",
+                &[&(block_height as i64)],
+            )
+            .await?;
+        // Delivered on commit, so listeners can read the whole block
+        transaction
+            .execute(
+                "SELECT pg_notify($1, $2)",
+                &[&BLOCKS_CHANNEL, &block_height.to_string()],
+            )
+            .await?;
+
+        transaction.commit().await?;
+        // Kept until committed, so that a failed block can be written again
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+}
+
+/// Key of the advisory lock that lets one connection at a time migrate, so
+/// that backfill workers starting together don't apply a migration twice
+const MIGRATION_LOCK: i64 = 0x6175726f7261; // "aurora"
+
+async fn migrate(client: &mut Client) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    let result = apply_migrations(client).await;
+    client
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    result
+}
+
+async fn apply_migrations(client: &mut Client) -> Result<(), tokio_postgres::Error> {
+    client
+        .batch_execute("CREATE TABLE IF NOT EXISTS aurora_migrations (version INTEGER PRIMARY KEY)")
+        .await?;
+    let applied: i64 = client
+        .query_one("SELECT COUNT(*) FROM aurora_migrations", &[])
+        .await?
+        .get(0);
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        tracing::info!("Applying Postgres migration {version}");
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration).await?;
+        transaction
+            .execute(
+                "INSERT INTO aurora_migrations (version) VALUES ($1)",
+                &[&(version as i32)],
+            )
+            .await?;
+        transaction.commit().await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl AuroraEventHandler for PostgresHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let index = self
+            .transactions
+            .iter()
+            .filter(|row| row.event.receipt_id == event.receipt_id)
+            .count() as i32;
+        self.transactions.push(TransactionRow {
+            event,
+            details,
+            index,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let (index, first_log_index) = self
+            .transactions
+            .iter()
+            .rev()
+            .find(|row| row.event.receipt_id == event.receipt_id)
+            .map_or((0, 0), |row| (row.index, row.details.first_log_index));
+        let tx_hash =
+            hex::decode(event.aurora_tx_hash.trim_start_matches("0x")).map_err(|err| {
+                format!(
+                    "Log has an invalid transaction hash {}: {err}",
+                    event.aurora_tx_hash
+                )
+            })?;
+        self.logs.push(LogRow {
+            block_log_index: (first_log_index + event.log_index) as i32,
+            event,
+            index,
+            tx_hash,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        self.write_block(block_height).await?;
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let row = self
+            .client
+            .query_opt("SELECT last_flushed_height FROM aurora_checkpoint", &[])
+            .await?;
+        Ok(row.map(|row| row.get::<_, i64>(0) as BlockHeight))
+    }
+}
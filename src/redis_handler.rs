@@ -1,38 +1,270 @@
+use std::time::Instant;
+
 use async_trait::async_trait;
-use inevents_redis::RedisEventStream;
 use inindexer::near_indexer_primitives::types::BlockHeight;
 use intear_events::events::aurora::transaction::AuroraTransactionEvent;
 use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisResult, Script};
+use serde::{Deserialize, Serialize};
 
-use crate::AuroraEventHandler;
+use crate::format::{EthereumBlockEvent, EthereumLogEvent, EthereumTransactionEvent, EventFormat};
+use crate::metrics::Metrics;
+use crate::{
+    AuroraBlockEvent, AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails,
+};
 
-pub struct PushToRedisStream {
-    transactions_stream: RedisEventStream<AuroraTransactionEvent>,
+/// Transaction event as it's pushed to the stream. The signature is an
+/// optional extra field, so existing consumers can keep parsing the event.
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedTransactionEvent {
+    #[serde(flatten)]
+    event: AuroraTransactionEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// Default key the last flushed block height is stored at
+pub const CHECKPOINT_KEY: &str = "aurora_indexer:last_flushed_height";
+
+/// Adds the entries of a block to streams and raises the checkpoint, in one
+/// atomic call.
+///
+/// `KEYS` are the streams, followed by the checkpoint key if there is one.
+/// `ARGV` is the block height, the stream size limit, the number of streams,
+/// and for each stream the number of entries followed by the entries. Entries
+/// whose ID is at or below the last ID of their stream were written before and
+/// are skipped, since `XADD` would fail on them, and Redis doesn't roll back
+/// the commands that already ran. Returns the number of entries added.
+const WRITE_BLOCK_SCRIPT: &str = r#"
+local height = tonumber(ARGV[1])
+local arg = 4
+local added = 0
+for stream_index = 1, tonumber(ARGV[3]) do
+    local stream = KEYS[stream_index]
+    local last_height, last_index = -1, -1
+    if redis.call('EXISTS', stream) == 1 then
+        local info = redis.call('XINFO', 'STREAM', stream)
+        for i = 1, #info, 2 do
+            if info[i] == 'last-generated-id' then
+                local ms, seq = string.match(info[i + 1], '(%d+)-(%d+)')
+                last_height, last_index = tonumber(ms), tonumber(seq)
+            end
+        end
+    end
+    local count = tonumber(ARGV[arg])
+    arg = arg + 1
+    for index = 0, count - 1 do
+        if height > last_height or (height == last_height and index > last_index) then
+            redis.call('XADD', stream, 'MAXLEN', '~', ARGV[2], ARGV[1] .. '-' .. index, 'event', ARGV[arg])
+            added = added + 1
+        end
+        arg = arg + 1
+    end
+end
+local checkpoint_key = KEYS[tonumber(ARGV[3]) + 1]
+if checkpoint_key then
+    local checkpoint = tonumber(redis.call('GET', checkpoint_key) or '-1')
+    if height > checkpoint then
+        redis.call('SET', checkpoint_key, ARGV[1])
+    end
+end
+return added
+"#;
+
+/// Writes blocks to Redis streams with entry IDs `<block height>-<index>` and
+/// the JSON event in the `event` field, like `inevents-redis` streams.
+///
+/// Writing a block again, or a block older than the last one, only adds the
+/// entries that aren't in the streams yet, and the checkpoint never moves
+/// back.
+pub(crate) struct StreamWriter {
+    script: Script,
     max_stream_size: usize,
 }
 
+impl StreamWriter {
+    pub(crate) fn new(max_stream_size: usize) -> Self {
+        Self {
+            script: Script::new(WRITE_BLOCK_SCRIPT),
+            max_stream_size,
+        }
+    }
+
+    /// Adds the entries of each stream and raises the checkpoint to
+    /// `block_height`. Returns the number of entries added.
+    pub(crate) async fn write_block(
+        &self,
+        connection: &mut ConnectionManager,
+        block_height: BlockHeight,
+        streams: &[(&str, &[String])],
+        checkpoint_key: Option<&str>,
+    ) -> RedisResult<usize> {
+        let mut invocation = self.script.prepare_invoke();
+        invocation
+            .arg(block_height)
+            .arg(self.max_stream_size)
+            .arg(streams.len());
+        for (stream, entries) in streams {
+            invocation.key(*stream).arg(entries.len());
+            for entry in *entries {
+                invocation.arg(entry);
+            }
+        }
+        if let Some(checkpoint_key) = checkpoint_key {
+            invocation.key(checkpoint_key);
+        }
+        invocation.invoke_async(connection).await
+    }
+}
+
+/// Pushes events to the `aurora_transaction`, `aurora_log` and `aurora_block`
+/// streams. Blocks are always in the Ethereum-style format.
+///
+/// Each block is written by a [`StreamWriter`] in a single script call
+/// together with its height at [`CHECKPOINT_KEY`], so the checkpoint always
+/// matches the streams. Blocks that were already written, in full or in part,
+/// can be written again, which makes restarts and re-indexing safe.
+pub struct PushToRedisStream {
+    connection: ConnectionManager,
+    writer: StreamWriter,
+    transaction_format: EventFormat,
+    log_format: EventFormat,
+    checkpoint_key: String,
+    /// Serialized events of the current block
+    transactions: Vec<String>,
+    logs: Vec<String>,
+    blocks: Vec<String>,
+    metrics: Metrics,
+}
+
 impl PushToRedisStream {
     pub async fn new(connection: ConnectionManager, max_stream_size: usize) -> Self {
         Self {
-            transactions_stream: RedisEventStream::new(
-                connection.clone(),
-                AuroraTransactionEvent::ID,
-            ),
-            max_stream_size,
+            connection,
+            writer: StreamWriter::new(max_stream_size),
+            transaction_format: EventFormat::Legacy,
+            log_format: EventFormat::Legacy,
+            checkpoint_key: CHECKPOINT_KEY.to_owned(),
+            transactions: Vec::new(),
+            logs: Vec::new(),
+            blocks: Vec::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    pub fn with_transaction_format(mut self, format: EventFormat) -> Self {
+        self.transaction_format = format;
+        self
+    }
+
+    pub fn with_log_format(mut self, format: EventFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    pub fn with_checkpoint_key(mut self, key: impl Into<String>) -> Self {
+        self.checkpoint_key = key.into();
+        self
+    }
+
+    /// Records write latency and errors with the `redis` sink label
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+/// `event` field of a transaction's stream entry
+pub(crate) fn transaction_entry(
+    format: EventFormat,
+    event: AuroraTransactionEvent,
+    details: TransactionDetails,
+) -> serde_json::Result<String> {
+    match format {
+        EventFormat::Legacy => serde_json::to_string(&SignedTransactionEvent {
+            event,
+            signature: details.signature,
+        }),
+        EventFormat::Ethereum => {
+            serde_json::to_string(&EthereumTransactionEvent::new(&event, &details))
         }
     }
 }
 
+/// `event` field of a log's stream entry
+pub(crate) fn log_entry(format: EventFormat, event: &AuroraLogEvent) -> serde_json::Result<String> {
+    match format {
+        EventFormat::Legacy => serde_json::to_string(event),
+        EventFormat::Ethereum => serde_json::to_string(&EthereumLogEvent::new(event)),
+    }
+}
+
 #[async_trait]
 impl AuroraEventHandler for PushToRedisStream {
-    async fn handle_transaction(&mut self, event: AuroraTransactionEvent) {
-        self.transactions_stream.add_event(event);
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        self.transactions
+            .push(transaction_entry(self.transaction_format, event, details)?);
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        self.logs.push(log_entry(self.log_format, &event)?);
+        Ok(())
+    }
+
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        self.blocks
+            .push(serde_json::to_string(&EthereumBlockEvent::new(&event))?);
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "redis_flush",
+        skip_all,
+        fields(transactions = self.transactions.len(), logs = self.logs.len())
+    )]
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        let started = Instant::now();
+        let written = self
+            .writer
+            .write_block(
+                &mut self.connection,
+                block_height,
+                &[
+                    (AuroraTransactionEvent::ID, &self.transactions),
+                    (AuroraLogEvent::ID, &self.logs),
+                    (AuroraBlockEvent::ID, &self.blocks),
+                ],
+                Some(&self.checkpoint_key),
+            )
+            .await;
+        if let Err(err) = written {
+            self.metrics.sink_errors.with_label_values(&["redis"]).inc();
+            return Err(format!("Failed to write block {block_height}: {err}").into());
+        }
+        self.metrics
+            .sink_write_duration
+            .with_label_values(&["redis"])
+            .observe(started.elapsed().as_secs_f64());
+        // Kept until written, so that a failed block can be written again
+        self.transactions.clear();
+        self.logs.clear();
+        self.blocks.clear();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.clear();
+        self.logs.clear();
+        self.blocks.clear();
+        Ok(())
     }
 
-    async fn flush_events(&mut self, block_height: BlockHeight) {
-        self.transactions_stream
-            .flush_events(block_height, self.max_stream_size)
-            .await
-            .expect("Failed to flush transactions stream");
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        Ok(self.connection.get(&self.checkpoint_key).await?)
     }
 }
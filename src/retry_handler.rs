@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+
+use crate::health::Health;
+use crate::{
+    AuroraBlockEvent, AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    /// Number of attempts before the error is returned, `None` to retry forever
+    pub max_attempts: Option<u32>,
+    /// Delay after the first failure, doubled after every attempt ...
+    pub initial_backoff: Duration,
+    /// ... up to this
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+struct Backoff<'a> {
+    config: &'a RetryConfig,
+    health: Option<&'a Health>,
+    attempts: u32,
+}
+
+impl<'a> Backoff<'a> {
+    fn new(config: &'a RetryConfig, health: Option<&'a Health>) -> Self {
+        Self {
+            config,
+            health,
+            attempts: 0,
+        }
+    }
+
+    /// Waits before the next attempt, or returns the error if there are no
+    /// attempts left
+    async fn failed(&mut self, operation: &str, err: HandlerError) -> Result<(), HandlerError> {
+        self.attempts += 1;
+        if let Some(health) = self.health {
+            health.sink_failed(&err);
+        }
+        if self
+            .config
+            .max_attempts
+            .is_some_and(|max_attempts| self.attempts >= max_attempts)
+        {
+            return Err(err);
+        }
+        let delay = self
+            .config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(self.attempts - 1))
+            .min(self.config.max_backoff);
+        tracing::warn!(
+            attempt = self.attempts,
+            ?delay,
+            error = %err,
+            "Failed to {operation}, retrying"
+        );
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+}
+
+/// Retries failed calls of the inner handler with exponential backoff, so that
+/// a sink that is temporarily unavailable pauses indexing instead of stopping
+/// it.
+///
+/// The inner handler must keep its state when a call fails, e.g. not drop the
+/// events of a block that failed to flush.
+pub struct RetryHandler<H> {
+    pub handler: H,
+    config: RetryConfig,
+    health: Option<Health>,
+}
+
+impl<H: AuroraEventHandler> RetryHandler<H> {
+    pub fn new(handler: H, config: RetryConfig) -> Self {
+        Self {
+            handler,
+            config,
+            health: None,
+        }
+    }
+
+    /// Marks the indexer as not ready while calls are failing
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = Some(health);
+        self
+    }
+}
+
+#[async_trait]
+impl<H: AuroraEventHandler> AuroraEventHandler for RetryHandler<H> {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self
+                .handler
+                .handle_transaction(event.clone(), details.clone())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => backoff.failed("handle transaction", err).await?,
+            }
+        }
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.handle_log(event.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(err) => backoff.failed("handle log", err).await?,
+            }
+        }
+    }
+
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.handle_block(event.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(err) => backoff.failed("handle block", err).await?,
+            }
+        }
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.flush_events(block_height).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    backoff
+                        .failed(&format!("flush block {block_height}"), err)
+                        .await?
+                }
+            }
+        }
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.discard_events().await {
+                Ok(()) => return Ok(()),
+                Err(err) => backoff.failed("discard events", err).await?,
+            }
+        }
+    }
+
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.finish().await {
+                Ok(()) => return Ok(()),
+                Err(err) => backoff.failed("finish", err).await?,
+            }
+        }
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let mut backoff = Backoff::new(&self.config, self.health.as_ref());
+        loop {
+            match self.handler.last_flushed_height().await {
+                Ok(height) => return Ok(height),
+                Err(err) => backoff.failed("read checkpoint", err).await?,
+            }
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::sync::Arc;
+
+use aurora_engine_types::U256;
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::post;
+use axum::Router;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls, Row};
+
+use crate::bloom::Bloom;
+use crate::format::hex_bytes;
+
+/// Widest block range `eth_getLogs` accepts
+pub const MAX_BLOCK_RANGE: BlockHeight = 10_000;
+/// Most logs `eth_getLogs` returns, a query that matches more fails
+pub const MAX_LOGS: usize = 10_000;
+
+/// Execution order of transactions within their block, as the `postgres` sink
+/// stores it. Rows written before it stored positions come last, ordered by
+/// receipt so that the order is stable across queries.
+const TRANSACTION_ORDER: &str = "transaction_index NULLS LAST, receipt_id, index";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn invalid_request(message: impl Into<String>) -> Self {
+        Self {
+            code: -32600,
+            message: message.into(),
+        }
+    }
+
+    fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("Method {method} not found"),
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: -32602,
+            message: message.into(),
+        }
+    }
+
+    fn limit_exceeded(message: impl Into<String>) -> Self {
+        Self {
+            code: -32005,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for RpcError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        tracing::error!("Query failed: {err}");
+        Self {
+            code: -32603,
+            message: "Internal error".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn decode(self, len: usize) -> Result<Vec<Vec<u8>>, RpcError> {
+        match self {
+            Self::One(value) => Ok(vec![bytes(&value, len)?]),
+            Self::Many(values) => values.iter().map(|value| bytes(value, len)).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogFilter {
+    from_block: Option<String>,
+    to_block: Option<String>,
+    block_hash: Option<String>,
+    address: Option<OneOrMany>,
+    #[serde(default)]
+    topics: Vec<Option<OneOrMany>>,
+}
+
+/// Ethereum JSON-RPC methods answered from the tables of
+/// [`PostgresHandler`](crate::postgres_handler::PostgresHandler):
+/// `eth_blockNumber`, `eth_getBlockByNumber`, `eth_getTransactionByHash`,
+/// `eth_getTransactionReceipt` and `eth_getLogs`.
+///
+/// The indexer doesn't know Aurora block hashes, nonces, gas prices or
+/// signatures, so `blockHash` is `null` and the other fields are left out.
+pub struct EthRpc {
+    client: Client,
+}
+
+impl EthRpc {
+    pub async fn connect(url: &str) -> Result<Self, tokio_postgres::Error> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::error!("Postgres connection error: {err}");
+            }
+        });
+        Ok(Self::new(client))
+    }
+
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Response to a JSON-RPC request or batch
+    pub async fn handle(&self, body: &str) -> Value {
+        match serde_json::from_str::<Value>(body) {
+            Ok(Value::Array(requests)) if !requests.is_empty() => {
+                let mut responses = Vec::with_capacity(requests.len());
+                for request in requests {
+                    responses.push(self.handle_request(request).await);
+                }
+                Value::Array(responses)
+            }
+            Ok(request) => self.handle_request(request).await,
+            Err(err) => response(
+                Value::Null,
+                Err(RpcError {
+                    code: -32700,
+                    message: format!("Parse error: {err}"),
+                }),
+            ),
+        }
+    }
+
+    async fn handle_request(&self, request: Value) -> Value {
+        match serde_json::from_value::<Request>(request) {
+            Ok(Request { id, method, params }) => response(id, self.call(&method, &params).await),
+            Err(err) => response(Value::Null, Err(RpcError::invalid_request(err.to_string()))),
+        }
+    }
+
+    pub async fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "eth_blockNumber" => Ok(quantity(self.latest_block().await?)),
+            "eth_getBlockByNumber" => {
+                let number = self.block_number(&param::<String>(params, 0)?).await?;
+                self.block(number, param::<Option<bool>>(params, 1)?.unwrap_or(false))
+                    .await
+            }
+            "eth_getTransactionByHash" => {
+                let hash = bytes(&param::<String>(params, 0)?, 32)?;
+                let transactions = self
+                    .transactions(
+                        "SELECT block_height FROM aurora_transactions WHERE tx_hash = $1",
+                        "t.tx_hash = $1",
+                        &[&hash],
+                    )
+                    .await?;
+                Ok(transactions.first().map_or(Value::Null, transaction_object))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash = bytes(&param::<String>(params, 0)?, 32)?;
+                self.receipt(&hash).await
+            }
+            "eth_getLogs" => self.get_logs(param(params, 0)?).await,
+            other => Err(RpcError::method_not_found(other)),
+        }
+    }
+
+    /// Last block the indexer flushed
+    async fn latest_block(&self) -> Result<BlockHeight, RpcError> {
+        let row = self
+            .client
+            .query_opt("SELECT last_flushed_height FROM aurora_checkpoint", &[])
+            .await?;
+        Ok(row.map_or(0, |row| row.get::<_, i64>(0) as BlockHeight))
+    }
+
+    async fn block_number(&self, tag: &str) -> Result<BlockHeight, RpcError> {
+        match tag {
+            "latest" | "safe" | "finalized" | "pending" => self.latest_block().await,
+            "earliest" => Ok(0),
+            number => parse_quantity(number),
+        }
+    }
+
+    async fn block(&self, number: BlockHeight, full: bool) -> Result<Value, RpcError> {
+        if number > self.latest_block().await? {
+            return Ok(Value::Null);
+        }
+        let transactions = self.transactions("$1", "TRUE", &[&(number as i64)]).await?;
+        let timestamp = transactions.first().map(|row| {
+            quantity(row.get::<_, i64>("block_timestamp_nanosec") as u64 / 1_000_000_000)
+        });
+        let gas_used = transactions
+            .iter()
+            .map(|row| row.get::<_, i64>("gas_used") as u64)
+            .sum::<u64>();
+        Ok(json!({
+            "number": quantity(number),
+            "hash": null,
+            "timestamp": timestamp,
+            "gasUsed": quantity(gas_used),
+            "transactions": transactions
+                .iter()
+                .map(|row| {
+                    if full {
+                        transaction_object(row)
+                    } else {
+                        Value::String(hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")))
+                    }
+                })
+                .collect::<Vec<_>>(),
+            "uncles": [],
+        }))
+    }
+
+    async fn receipt(&self, hash: &[u8]) -> Result<Value, RpcError> {
+        let blocks = "SELECT block_height FROM aurora_transactions WHERE tx_hash = $1";
+        let transactions = self
+            .transactions(blocks, "t.tx_hash = $1", &[&hash])
+            .await?;
+        let Some(row) = transactions.first() else {
+            return Ok(Value::Null);
+        };
+        let logs = self.logs(blocks, "l.tx_hash = $1", None, &[&hash]).await?;
+        let mut logs_bloom = Bloom::default();
+        for log in &logs {
+            logs_bloom.accrue(&log.get::<_, Vec<u8>>("address"));
+            for topic in log.get::<_, Vec<Vec<u8>>>("topics") {
+                logs_bloom.accrue(&topic);
+            }
+        }
+        let mut receipt = json!({
+            "transactionHash": hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")),
+            "transactionIndex": position(row, "transaction_index"),
+            "blockHash": null,
+            "blockNumber": quantity(row.get::<_, i64>("block_height") as u64),
+            "from": hex_bytes(&row.get::<_, Vec<u8>>("from_address")),
+            "to": row.get::<_, Option<Vec<u8>>>("to_address").map(|to| hex_bytes(&to)),
+            "gasUsed": quantity(row.get::<_, i64>("gas_used") as u64),
+            "cumulativeGasUsed": row
+                .get::<_, Option<i64>>("cumulative_gas_used")
+                .map(|gas| quantity(gas as u64)),
+            "contractAddress": row
+                .get::<_, Option<Vec<u8>>>("contract_address")
+                .map(|address| hex_bytes(&address)),
+            "logs": logs.iter().map(log_object).collect::<Vec<_>>(),
+            "logsBloom": logs_bloom.to_hex(),
+            "status": if row.get::<_, String>("status") == "succeed" { "0x1" } else { "0x0" },
+        });
+        if let Some(chain_id) = row.get::<_, Option<i64>>("chain_id") {
+            receipt["chainId"] = quantity(chain_id as u64);
+        }
+        Ok(receipt)
+    }
+
+    async fn get_logs(&self, filter: LogFilter) -> Result<Value, RpcError> {
+        if filter.block_hash.is_some() {
+            return Err(RpcError::invalid_params(
+                "blockHash isn't supported, use fromBlock and toBlock",
+            ));
+        }
+        let from_block = self
+            .block_number(filter.from_block.as_deref().unwrap_or("latest"))
+            .await?;
+        let to_block = self
+            .block_number(filter.to_block.as_deref().unwrap_or("latest"))
+            .await?;
+        if to_block < from_block {
+            return Ok(json!([]));
+        }
+        if to_block - from_block >= MAX_BLOCK_RANGE {
+            return Err(RpcError::limit_exceeded(format!(
+                "Block range is limited to {MAX_BLOCK_RANGE} blocks"
+            )));
+        }
+
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> =
+            vec![Box::new(from_block as i64), Box::new(to_block as i64)];
+        let mut conditions = vec!["TRUE".to_owned()];
+        if let Some(address) = filter.address {
+            params.push(Box::new(address.decode(20)?));
+            conditions.push(format!("l.address = ANY(${})", params.len()));
+        }
+        for (position, topics) in filter.topics.into_iter().enumerate() {
+            let Some(topics) = topics else {
+                continue;
+            };
+            params.push(Box::new(topics.decode(32)?));
+            conditions.push(format!(
+                "l.topics[{}] = ANY(${})",
+                position + 1,
+                params.len()
+            ));
+        }
+        let params = params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        let logs = self
+            .logs(
+                "SELECT generate_series($1::BIGINT, $2::BIGINT)",
+                &conditions.join(" AND "),
+                Some(MAX_LOGS + 1),
+                &params,
+            )
+            .await?;
+        if logs.len() > MAX_LOGS {
+            return Err(RpcError::limit_exceeded(format!(
+                "Query returned more than {MAX_LOGS} logs, use a smaller block range"
+            )));
+        }
+        Ok(Value::Array(logs.iter().map(log_object).collect()))
+    }
+
+    /// Transactions matching `condition` in the blocks selected by `blocks`,
+    /// in execution order
+    async fn transactions(
+        &self,
+        blocks: &str,
+        condition: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, RpcError> {
+        Ok(self
+            .client
+            .query(
+                &format!(
+                    "SELECT t.*, t.value::TEXT AS value_text, d.contract_address
+                    FROM aurora_transactions t
+                    LEFT JOIN aurora_deployments d USING (block_height, receipt_id, index)
+                    WHERE t.block_height IN ({blocks}) AND {condition}
+                    ORDER BY t.block_height, {TRANSACTION_ORDER}"
+                ),
+                params,
+            )
+            .await?)
+    }
+
+    /// Up to `limit` logs matching `condition` in the blocks selected by
+    /// `blocks`, with their transaction's position
+    async fn logs(
+        &self,
+        blocks: &str,
+        condition: &str,
+        limit: Option<usize>,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, RpcError> {
+        let limit = limit.map_or(String::new(), |limit| format!("LIMIT {limit}"));
+        Ok(self
+            .client
+            .query(
+                &format!(
+                    "SELECT l.*, t.transaction_index
+                    FROM aurora_logs l
+                    JOIN aurora_transactions t USING (block_height, receipt_id, index)
+                    WHERE l.block_height IN ({blocks}) AND {condition}
+                    ORDER BY l.block_height, l.block_log_index NULLS LAST, l.receipt_id, l.index, l.log_index
+                    {limit}"
+                ),
+                params,
+            )
+            .await?)
+    }
+}
+
+/// `POST /` with JSON-RPC requests
+pub fn router(rpc: Arc<EthRpc>) -> Router {
+    Router::new().route(
+        "/",
+        post(move |body: String| async move {
+            (
+                [(CONTENT_TYPE, "application/json")],
+                rpc.handle(&body).await.to_string(),
+            )
+        }),
+    )
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(RpcError { code, message }) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn param<T: DeserializeOwned>(params: &Value, index: usize) -> Result<T, RpcError> {
+    serde_json::from_value(params.get(index).cloned().unwrap_or(Value::Null))
+        .map_err(|err| RpcError::invalid_params(format!("Invalid parameter {index}: {err}")))
+}
+
+fn bytes(value: &str, len: usize) -> Result<Vec<u8>, RpcError> {
+    hex::decode(value.trim_start_matches("0x"))
+        .ok()
+        .filter(|bytes| bytes.len() == len)
+        .ok_or_else(|| RpcError::invalid_params(format!("Expected {len} hex bytes, got {value}")))
+}
+
+fn parse_quantity(value: &str) -> Result<u64, RpcError> {
+    value
+        .strip_prefix("0x")
+        .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| RpcError::invalid_params(format!("Invalid block number {value}")))
+}
+
+fn quantity(value: u64) -> Value {
+    Value::String(format!("{value:#x}"))
+}
+
+/// Position in the Aurora block, `null` for rows written before the `postgres`
+/// sink stored it
+fn position(row: &Row, column: &str) -> Value {
+    row.get::<_, Option<i32>>(column)
+        .map_or(Value::Null, |position| quantity(position as u64))
+}
+
+fn transaction_object(row: &Row) -> Value {
+    let value = U256::from_dec_str(&row.get::<_, String>("value_text")).unwrap_or_default();
+    let mut transaction = json!({
+        "hash": hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")),
+        "blockHash": null,
+        "blockNumber": quantity(row.get::<_, i64>("block_height") as u64),
+        "transactionIndex": position(row, "transaction_index"),
+        "from": hex_bytes(&row.get::<_, Vec<u8>>("from_address")),
+        "to": row.get::<_, Option<Vec<u8>>>("to_address").map(|to| hex_bytes(&to)),
+        "value": format!("{value:#x}"),
+        "input": hex_bytes(&row.get::<_, Vec<u8>>("input")),
+    });
+    if let Some(chain_id) = row.get::<_, Option<i64>>("chain_id") {
+        transaction["chainId"] = quantity(chain_id as u64);
+    }
+    transaction
+}
+
+fn log_object(row: &Row) -> Value {
+    json!({
+        "address": hex_bytes(&row.get::<_, Vec<u8>>("address")),
+        "topics": row
+            .get::<_, Vec<Vec<u8>>>("topics")
+            .iter()
+            .map(|topic| hex_bytes(topic))
+            .collect::<Vec<_>>(),
+        "data": hex_bytes(&row.get::<_, Vec<u8>>("data")),
+        "blockHash": null,
+        "blockNumber": quantity(row.get::<_, i64>("block_height") as u64),
+        "transactionHash": hex_bytes(&row.get::<_, Vec<u8>>("tx_hash")),
+        "transactionIndex": position(row, "transaction_index"),
+        "logIndex": position(row, "block_log_index"),
+        "removed": false,
+    })
+}
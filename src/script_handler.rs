@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use serde::Serialize;
+
+use crate::format::{EthereumLogEvent, EthereumTransactionEvent};
+use crate::metrics::Metrics;
+use crate::redis_handler::StreamWriter;
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+/// Prefix of the streams the script sink writes to, so that they don't mix
+/// with the ones of the `redis` sink
+pub const DEFAULT_STREAM_PREFIX: &str = "script:";
+/// Key of the script sink's checkpoint, separate from the `redis` sink's one
+pub const DEFAULT_CHECKPOINT_KEY: &str = "aurora_indexer:script:last_flushed_height";
+/// Longest stream name `emit` accepts
+const MAX_STREAM_NAME_LEN: usize = 128;
+
+/// Limits a script runs with, a call that exceeds one fails
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptLimits {
+    /// Operations per call, roughly the number of evaluated expressions
+    pub max_operations: u64,
+    pub max_call_levels: usize,
+    /// Bytes
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 100_000,
+            max_call_levels: 32,
+            max_string_size: 1024 * 1024,
+            max_array_size: 10_000,
+            max_map_size: 10_000,
+        }
+    }
+}
+
+/// Rhai script that transforms events.
+///
+/// The script can define `on_transaction(tx)` and `on_log(log)`, which get the
+/// event in the Ethereum format as a map. Whatever they return is kept in
+/// place of the event, `()` drops it. Derived events are sent to other streams
+/// with `emit(stream, event)`, where the stream name is made of ASCII letters,
+/// digits, `_`, `-`, `.` and `:`, anything else fails the call. Events without
+/// a function are kept unchanged.
+///
+/// Scripts can't access the file system or network, `eval` and modules are
+/// disabled, and each call runs within [`ScriptLimits`].
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    has_on_transaction: bool,
+    has_on_log: bool,
+    /// Events passed to `emit` during the current call
+    emitted: Arc<Mutex<Vec<(String, Dynamic)>>>,
+}
+
+impl Script {
+    pub fn load(
+        path: impl Into<PathBuf>,
+        limits: ScriptLimits,
+    ) -> Result<Self, Box<EvalAltResult>> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(limits.max_operations)
+            .set_max_call_levels(limits.max_call_levels)
+            .set_max_string_size(limits.max_string_size)
+            .set_max_array_size(limits.max_array_size)
+            .set_max_map_size(limits.max_map_size)
+            .set_max_modules(0)
+            .disable_symbol("eval")
+            .on_print(|text| tracing::info!("Script: {text}"))
+            .on_debug(|text, _source, position| tracing::debug!("Script at {position}: {text}"));
+
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let sink = emitted.clone();
+        engine.register_fn(
+            "emit",
+            move |stream: &str, event: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                if !is_stream_name(stream) {
+                    return Err(format!("Invalid stream name {stream:?}").into());
+                }
+                sink.lock().unwrap().push((stream.to_owned(), event));
+                Ok(())
+            },
+        );
+
+        let ast = engine.compile_file(path.into())?;
+        let defines = |name: &str| ast.iter_functions().any(|function| function.name == name);
+        Ok(Self {
+            has_on_transaction: defines("on_transaction"),
+            has_on_log: defines("on_log"),
+            engine,
+            ast,
+            emitted,
+        })
+    }
+
+    /// Events to push for a transaction, by stream name
+    pub fn on_transaction(
+        &self,
+        event: &AuroraTransactionEvent,
+        details: &TransactionDetails,
+    ) -> Result<Vec<(String, serde_json::Value)>, Box<EvalAltResult>> {
+        self.call(
+            self.has_on_transaction.then_some("on_transaction"),
+            AuroraTransactionEvent::ID,
+            EthereumTransactionEvent::new(event, details),
+        )
+    }
+
+    /// Events to push for a log, by stream name
+    pub fn on_log(
+        &self,
+        event: &AuroraLogEvent,
+    ) -> Result<Vec<(String, serde_json::Value)>, Box<EvalAltResult>> {
+        self.call(
+            self.has_on_log.then_some("on_log"),
+            AuroraLogEvent::ID,
+            EthereumLogEvent::new(event),
+        )
+    }
+
+    fn call(
+        &self,
+        function: Option<&str>,
+        stream: &str,
+        event: impl Serialize,
+    ) -> Result<Vec<(String, serde_json::Value)>, Box<EvalAltResult>> {
+        let event = rhai::serde::to_dynamic(event)?;
+        let result = match function {
+            Some(function) => {
+                self.engine
+                    .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, (event,))
+            }
+            None => Ok(event),
+        };
+        // Taken before checking the result, so that a failed call doesn't
+        // leave its events for the next one
+        let emitted = std::mem::take(&mut *self.emitted.lock().unwrap());
+        let kept = result?;
+
+        let mut events = Vec::new();
+        if !kept.is_unit() {
+            events.push((stream.to_owned(), rhai::serde::from_dynamic(&kept)?));
+        }
+        for (stream, event) in emitted {
+            events.push((stream, rhai::serde::from_dynamic(&event)?));
+        }
+        Ok(events)
+    }
+}
+
+fn is_stream_name(stream: &str) -> bool {
+    !stream.is_empty()
+        && stream.len() <= MAX_STREAM_NAME_LEN
+        && stream
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"_-.:".contains(&byte))
+}
+
+/// Runs events through a [`Script`] and pushes the results to Redis streams.
+///
+/// Stream names get [`DEFAULT_STREAM_PREFIX`], so kept transactions go to
+/// `script:aurora_transaction` by default, and the checkpoint is kept at
+/// [`DEFAULT_CHECKPOINT_KEY`]. That way the sink can run next to the `redis`
+/// sink on the same server. Blocks are written by a [`StreamWriter`] like the
+/// `redis` sink does, with the same entry IDs, so restarts resume from the
+/// checkpoint and the streams can be verified. A script error is logged and drops the
+/// event, since retrying wouldn't change the outcome, and is counted in the
+/// `aurora_indexer_script_dropped_events_total` metric.
+pub struct ScriptHandler {
+    script: Script,
+    connection: ConnectionManager,
+    writer: StreamWriter,
+    stream_prefix: String,
+    checkpoint_key: String,
+    /// Serialized events of the current block, by stream
+    streams: BTreeMap<String, Vec<String>>,
+    metrics: Metrics,
+}
+
+impl ScriptHandler {
+    pub fn new(script: Script, connection: ConnectionManager, max_stream_size: usize) -> Self {
+        Self {
+            script,
+            connection,
+            writer: StreamWriter::new(max_stream_size),
+            stream_prefix: DEFAULT_STREAM_PREFIX.to_owned(),
+            checkpoint_key: DEFAULT_CHECKPOINT_KEY.to_owned(),
+            streams: BTreeMap::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    pub fn with_stream_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.stream_prefix = prefix.into();
+        self
+    }
+
+    pub fn with_checkpoint_key(mut self, key: impl Into<String>) -> Self {
+        self.checkpoint_key = key.into();
+        self
+    }
+
+    /// Counts dropped events, and records write latency and errors with the
+    /// `script` sink label
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn push(&mut self, events: Vec<(String, serde_json::Value)>) {
+        for (stream, event) in events {
+            let stream = format!("{}{stream}", self.stream_prefix);
+            // Writing it as a stream would fail every time the block is retried
+            if stream == self.checkpoint_key {
+                tracing::error!(
+                    "Script emitted an event to the checkpoint key {stream}, dropping it"
+                );
+                self.dropped("emitted");
+                continue;
+            }
+            self.streams
+                .entry(stream)
+                .or_default()
+                .push(event.to_string());
+        }
+    }
+
+    fn dropped(&self, kind: &str) {
+        self.metrics
+            .script_dropped_events
+            .with_label_values(&[kind])
+            .inc();
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for ScriptHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        match self.script.on_transaction(&event, &details) {
+            Ok(events) => self.push(events),
+            Err(err) => {
+                tracing::error!(
+                    "Script failed on transaction {:#x}, dropping it: {err}",
+                    details.tx_hash
+                );
+                self.dropped("transaction");
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        match self.script.on_log(&event) {
+            Ok(events) => self.push(events),
+            Err(err) => {
+                tracing::error!(
+                    "Script failed on log {} of transaction {}, dropping it: {err}",
+                    event.log_index,
+                    event.aurora_tx_hash
+                );
+                self.dropped("log");
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        let started = Instant::now();
+        let streams = self
+            .streams
+            .iter()
+            .map(|(stream, entries)| (stream.as_str(), entries.as_slice()))
+            .collect::<Vec<_>>();
+        let written = self
+            .writer
+            .write_block(
+                &mut self.connection,
+                block_height,
+                &streams,
+                Some(&self.checkpoint_key),
+            )
+            .await;
+        if let Err(err) = written {
+            self.metrics
+                .sink_errors
+                .with_label_values(&["script"])
+                .inc();
+            return Err(format!("Failed to write block {block_height}: {err}").into());
+        }
+        self.metrics
+            .sink_write_duration
+            .with_label_values(&["script"])
+            .observe(started.elapsed().as_secs_f64());
+        // Kept until written, so that a failed block can be written again
+        self.streams.clear();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.streams.clear();
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        Ok(self.connection.get(&self.checkpoint_key).await?)
+    }
+}
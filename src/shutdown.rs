@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct State {
+    requested: AtomicBool,
+    in_block: AtomicBool,
+    changed: Notify,
+}
+
+/// Lets indexing be stopped between blocks, so that a block is never left
+/// half-written.
+///
+/// [`AuroraIndexer`](crate::AuroraIndexer) marks when it's in the middle of a
+/// block. After [`Shutdown::request`], it doesn't start a new block, and
+/// [`Shutdown::stopped`] resolves once the current one is flushed. The caller
+/// is expected to drop the indexer run at that point:
+///
+/// ```ignore
+/// tokio::select! {
+///     result = run_indexer(&mut indexer, provider, options) => result.unwrap(),
+///     () = shutdown.stopped() => {}
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    state: Arc<State>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request(&self) {
+        self.state.requested.store(true, Ordering::SeqCst);
+        self.state.changed.notify_waiters();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once shutdown was requested and no block is being processed
+    pub async fn stopped(&self) {
+        loop {
+            // Created before checking, so that a change in between isn't missed
+            let changed = self.state.changed.notified();
+            if self.is_requested() && !self.state.in_block.load(Ordering::SeqCst) {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Marks the start of a block. If shutdown was requested, this never
+    /// returns and waits for the indexer run to be dropped.
+    pub(crate) async fn block_started(&self) {
+        if self.state.in_block.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if self.is_requested() {
+            self.block_finished();
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub(crate) fn block_finished(&self) {
+        self.state.in_block.store(false, Ordering::SeqCst);
+        self.state.changed.notify_waiters();
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+const BUNDLED_SIGNATURES: &str = include_str!("../signatures.txt");
+
+/// Offline lookup table for function selectors and event topics.
+///
+/// Several signatures can share a selector, so every entry keeps all known
+/// candidates and [`SignatureDatabase::function_signature`] picks the one that
+/// fits the calldata best. Entries loaded later take precedence over earlier
+/// ones, so custom databases can override the bundled one.
+#[derive(Debug, Clone, Default)]
+pub struct SignatureDatabase {
+    functions: HashMap<[u8; 4], Vec<String>>,
+    events: HashMap<[u8; 32], Vec<String>>,
+}
+
+impl SignatureDatabase {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Database with the signatures shipped with the indexer
+    pub fn bundled() -> Self {
+        let mut database = Self::empty();
+        database
+            .extend_from_str(BUNDLED_SIGNATURES)
+            .expect("Bundled signature database is invalid");
+        database
+    }
+
+    /// Loads a 4byte-style signature file, see `signatures.txt` for the format
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        self.extend_from_str(&contents)
+    }
+
+    pub fn extend_from_str(&mut self, contents: &str) -> io::Result<()> {
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {}: {reason}: {line}", line_number + 1),
+                )
+            };
+            let (key, signature) = line
+                .split_once([' ', '\t', ','])
+                .ok_or_else(|| invalid("Expected a hash or kind followed by a signature"))?;
+            let signature = signature.trim();
+            if !signature.contains('(') || !signature.ends_with(')') {
+                return Err(invalid("Invalid signature"));
+            }
+            match key {
+                "function" => {
+                    let hash = aurora_engine_sdk::keccak(signature.as_bytes());
+                    self.add_function(hash.0[..4].try_into().unwrap(), signature);
+                }
+                "event" => {
+                    let hash = aurora_engine_sdk::keccak(signature.as_bytes());
+                    self.add_event(hash.0, signature);
+                }
+                hash => {
+                    let bytes = hex::decode(hash.trim_start_matches("0x"))
+                        .map_err(|_| invalid("Invalid hex"))?;
+                    if let Ok(selector) = <[u8; 4]>::try_from(bytes.as_slice()) {
+                        self.add_function(selector, signature);
+                    } else if let Ok(topic) = <[u8; 32]>::try_from(bytes.as_slice()) {
+                        self.add_event(topic, signature);
+                    } else {
+                        return Err(invalid("Expected a 4-byte selector or a 32-byte topic"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_function(&mut self, selector: [u8; 4], signature: &str) {
+        let candidates = self.functions.entry(selector).or_default();
+        candidates.retain(|existing| existing != signature);
+        candidates.insert(0, signature.to_owned());
+    }
+
+    fn add_event(&mut self, topic: [u8; 32], signature: &str) {
+        let candidates = self.events.entry(topic).or_default();
+        candidates.retain(|existing| existing != signature);
+        candidates.insert(0, signature.to_owned());
+    }
+
+    /// Best-match signature for the first 4 bytes of a transaction's input.
+    ///
+    /// When multiple signatures share a selector, the one whose ABI head size
+    /// matches the calldata length wins.
+    pub fn function_signature(&self, input: &[u8]) -> Option<&str> {
+        let selector: [u8; 4] = input.get(..4)?.try_into().unwrap();
+        let candidates = self.functions.get(&selector)?;
+        let arguments_length = input.len() - 4;
+        candidates
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, signature)| {
+                let score = match abi_head_size(signature) {
+                    Some(HeadSize::Static(words)) if arguments_length == words * 32 => 2,
+                    Some(HeadSize::Dynamic(words))
+                        if arguments_length >= words * 32 && arguments_length % 32 == 0 =>
+                    {
+                        1
+                    }
+                    _ => 0,
+                };
+                (score, std::cmp::Reverse(*index))
+            })
+            .map(|(_, signature)| signature.as_str())
+    }
+
+    /// Signature of the event identified by a log's first topic
+    pub fn event_signature(&self, topics: &[[u8; 32]]) -> Option<&str> {
+        self.events
+            .get(topics.first()?)?
+            .first()
+            .map(String::as_str)
+    }
+}
+
+enum HeadSize {
+    /// All arguments are static, the calldata is exactly this many words long
+    Static(usize),
+    /// Some arguments are dynamic, the calldata is at least this many words long
+    Dynamic(usize),
+}
+
+fn abi_head_size(signature: &str) -> Option<HeadSize> {
+    let arguments = signature.get(signature.find('(')? + 1..signature.len() - 1)?;
+    let mut words = 0;
+    let mut dynamic = false;
+    for argument in split_top_level(arguments) {
+        match static_words(argument) {
+            Some(size) => words += size,
+            None => {
+                words += 1;
+                dynamic = true;
+            }
+        }
+    }
+    Some(if dynamic {
+        HeadSize::Dynamic(words)
+    } else {
+        HeadSize::Static(words)
+    })
+}
+
+/// Number of words a static ABI type occupies, or `None` if it's dynamic
+fn static_words(ty: &str) -> Option<usize> {
+    if ty.ends_with("[]") {
+        return None;
+    }
+    if let Some(prefix) = ty.strip_suffix(']') {
+        let (element, length) = prefix.rsplit_once('[')?;
+        return Some(static_words(element)? * length.parse::<usize>().ok()?);
+    }
+    if let Some(components) = ty.strip_prefix('(').and_then(|ty| ty.strip_suffix(')')) {
+        return split_top_level(components)
+            .map(static_words)
+            .sum::<Option<usize>>();
+    }
+    match ty {
+        "string" | "bytes" => None,
+        _ => Some(1),
+    }
+}
+
+fn split_top_level(list: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut items = Vec::new();
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !list.is_empty() {
+        items.push(&list[start..]);
+    }
+    items.into_iter().map(str::trim)
+}
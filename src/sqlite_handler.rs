@@ -0,0 +1,267 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::format::{status_name, status_output};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+/// Schema migrations, applied in order and tracked in `PRAGMA user_version`.
+/// Never edit an existing one, add a new migration instead.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE aurora_transactions (
+    block_height INTEGER NOT NULL,
+    receipt_id TEXT NOT NULL,
+    "index" INTEGER NOT NULL,
+    block_timestamp_nanosec INTEGER NOT NULL,
+    transaction_id TEXT NOT NULL,
+    tx_hash BLOB NOT NULL,
+    chain_id INTEGER,
+    from_address BLOB NOT NULL,
+    to_address BLOB,
+    -- Decimal amount of wei, u256 doesn't fit into INTEGER
+    value TEXT NOT NULL,
+    input BLOB NOT NULL,
+    status TEXT NOT NULL,
+    output BLOB,
+    gas_used INTEGER NOT NULL,
+    signature TEXT,
+    PRIMARY KEY (block_height, receipt_id, "index")
+);
+CREATE INDEX aurora_transactions_tx_hash ON aurora_transactions (tx_hash);
+CREATE INDEX aurora_transactions_from_address ON aurora_transactions (from_address, block_height);
+CREATE INDEX aurora_transactions_to_address ON aurora_transactions (to_address, block_height);
+
+CREATE TABLE aurora_logs (
+    block_height INTEGER NOT NULL,
+    receipt_id TEXT NOT NULL,
+    "index" INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    block_timestamp_nanosec INTEGER NOT NULL,
+    tx_hash BLOB NOT NULL,
+    address BLOB NOT NULL,
+    topic0 BLOB,
+    topic1 BLOB,
+    topic2 BLOB,
+    topic3 BLOB,
+    data BLOB NOT NULL,
+    signature TEXT,
+    PRIMARY KEY (block_height, receipt_id, "index", log_index)
+);
+CREATE INDEX aurora_logs_tx_hash ON aurora_logs (tx_hash);
+CREATE INDEX aurora_logs_address ON aurora_logs (address, block_height);
+CREATE INDEX aurora_logs_topic0 ON aurora_logs (topic0, block_height);
+"#,
+    r#"
+CREATE TABLE aurora_checkpoint (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    last_flushed_height INTEGER NOT NULL
+);
+"#,
+];
+
+struct TransactionRow {
+    event: AuroraTransactionEvent,
+    details: TransactionDetails,
+    /// Position of the transaction within its receipt
+    index: i64,
+}
+
+struct LogRow {
+    event: AuroraLogEvent,
+    /// Position of the log's transaction within its receipt
+    index: i64,
+    /// Decoded `aurora_tx_hash`
+    tx_hash: Vec<u8>,
+}
+
+/// Stores events in an SQLite database, one database transaction per block.
+///
+/// Rows are keyed by `(block_height, receipt_id, index)`, so indexing the same
+/// blocks again doesn't create duplicates. The height of the last written
+/// block is stored in `aurora_checkpoint` in the same transaction.
+///
+/// Writes are synchronous, which is fine for the small databases this is
+/// meant for.
+pub struct SqliteHandler {
+    // `Connection` isn't `Sync`, handlers have to be
+    connection: Mutex<Connection>,
+    transactions: Vec<TransactionRow>,
+    logs: Vec<LogRow>,
+}
+
+impl SqliteHandler {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        // Setting the journal mode returns the new mode as a row
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        Self::new(connection)
+    }
+
+    pub fn new(mut connection: Connection) -> rusqlite::Result<Self> {
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            transactions: Vec::new(),
+            logs: Vec::new(),
+        })
+    }
+
+    fn write_block(&mut self, block_height: BlockHeight) -> rusqlite::Result<()> {
+        let connection = self.connection.get_mut().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut insert_transaction = transaction.prepare_cached(
+                r#"INSERT OR IGNORE INTO aurora_transactions (block_height, receipt_id, "index", block_timestamp_nanosec, transaction_id, tx_hash, chain_id, from_address, to_address, value, input, status, output, gas_used, signature)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"#,
+            )?;
+            for TransactionRow {
+                event,
+                details,
+                index,
+            } in &self.transactions
+            {
+                insert_transaction.execute(params![
+                    event.block_height as i64,
+                    event.receipt_id.to_string(),
+                    index,
+                    event.block_timestamp_nanosec as i64,
+                    event.transaction_id.to_string(),
+                    details.tx_hash.as_bytes(),
+                    event.chain_id.map(|chain_id| chain_id as i64),
+                    event.from.raw().as_bytes(),
+                    event.to.as_ref().map(|to| to.raw().as_bytes().to_vec()),
+                    event.value.raw().to_string(),
+                    event.input,
+                    status_name(&event.status),
+                    status_output(&event.status),
+                    details.gas_used as i64,
+                    details.signature,
+                ])?;
+            }
+
+            let mut insert_log = transaction.prepare_cached(
+                r#"INSERT OR IGNORE INTO aurora_logs (block_height, receipt_id, "index", log_index, block_timestamp_nanosec, tx_hash, address, topic0, topic1, topic2, topic3, data, signature)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"#,
+            )?;
+            for LogRow {
+                event,
+                index,
+                tx_hash,
+            } in &self.logs
+            {
+                let topic = |i: usize| event.topics.get(i).map(|topic| topic.to_vec());
+                insert_log.execute(params![
+                    event.block_height as i64,
+                    event.receipt_id.to_string(),
+                    index,
+                    event.log_index as i64,
+                    event.block_timestamp_nanosec as i64,
+                    tx_hash,
+                    event.address.raw().as_bytes(),
+                    topic(0),
+                    topic(1),
+                    topic(2),
+                    topic(3),
+                    event.data,
+                    event.signature,
+                ])?;
+            }
+        }
+        // Never moves back when older blocks are indexed again
+        transaction.execute(
+            "INSERT INTO aurora_checkpoint (id, last_flushed_height) VALUES (0, ?1)
+            ON CONFLICT (id) DO UPDATE SET last_flushed_height = max(last_flushed_height, excluded.last_flushed_height)",
+            params![block_height as i64],
+        )?;
+        transaction.commit()?;
+        // Kept until committed, so that a failed block can be written again
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        tracing::info!("Applying SQLite migration {version}");
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl AuroraEventHandler for SqliteHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let index = self
+            .transactions
+            .iter()
+            .filter(|row| row.event.receipt_id == event.receipt_id)
+            .count() as i64;
+        self.transactions.push(TransactionRow {
+            event,
+            details,
+            index,
+        });
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let index = self
+            .transactions
+            .iter()
+            .rev()
+            .find(|row| row.event.receipt_id == event.receipt_id)
+            .map_or(0, |row| row.index);
+        let tx_hash =
+            hex::decode(event.aurora_tx_hash.trim_start_matches("0x")).map_err(|err| {
+                format!(
+                    "Log has an invalid transaction hash {}: {err}",
+                    event.aurora_tx_hash
+                )
+            })?;
+        self.logs.push(LogRow {
+            event,
+            index,
+            tx_hash,
+        });
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, block_height: BlockHeight) -> Result<(), HandlerError> {
+        self.write_block(block_height)?;
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.transactions.clear();
+        self.logs.clear();
+        Ok(())
+    }
+
+    async fn last_flushed_height(&mut self) -> Result<Option<BlockHeight>, HandlerError> {
+        let height = self
+            .connection
+            .get_mut()
+            .unwrap()
+            .query_row(
+                "SELECT last_flushed_height FROM aurora_checkpoint",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?;
+        Ok(height.map(|height| height as BlockHeight))
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use async_trait::async_trait;
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use inindexer::neardata::NeardataProvider;
+use inindexer::{run_indexer, BlockRange, IndexerOptions, PreprocessTransactionsSettings};
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamMaxlen, StreamRangeReply};
+use redis::AsyncCommands;
+use serde::Deserialize;
+
+use crate::format::EventFormat;
+use crate::redis_handler::{log_entry, transaction_entry};
+use crate::{AuroraEventHandler, AuroraIndexer, AuroraLogEvent, HandlerError, TransactionDetails};
+
+/// Entries read from the stream at once
+const PAGE_SIZE: usize = 1000;
+
+/// Stream that [`StreamVerifier::repair`] adds the missing events of `stream` to
+pub fn repair_stream(stream: &str) -> String {
+    format!("{stream}.repair")
+}
+
+/// `event` fields of stream entries, by block
+pub type BlockEntries = BTreeMap<BlockHeight, Vec<String>>;
+
+/// Differences between the entries a stream has and the ones it should have
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamReport {
+    pub stream: String,
+    /// Events that aren't in the stream, by block
+    pub missing: BlockEntries,
+    /// Events that are in the stream more often than they should be, by block
+    pub duplicated: BlockEntries,
+    /// Blocks before the first entry of the stream, which were trimmed by its
+    /// maximum length, so their events aren't compared
+    pub trimmed: BTreeSet<BlockHeight>,
+}
+
+impl StreamReport {
+    pub fn compare(
+        stream: impl Into<String>,
+        expected: &BlockEntries,
+        found: &BlockEntries,
+    ) -> Self {
+        let mut report = Self {
+            stream: stream.into(),
+            missing: BTreeMap::new(),
+            duplicated: BTreeMap::new(),
+            trimmed: BTreeSet::new(),
+        };
+        let heights = expected.keys().chain(found.keys()).copied();
+        for height in heights.collect::<BTreeSet<_>>() {
+            let mut counts = HashMap::<&str, (usize, usize)>::new();
+            for entry in expected.get(&height).into_iter().flatten() {
+                counts.entry(entry.as_str()).or_default().0 += 1;
+            }
+            for entry in found.get(&height).into_iter().flatten() {
+                counts.entry(entry.as_str()).or_default().1 += 1;
+            }
+            // Keep the order of the block, so that repaired events are
+            // appended the way they were emitted
+            for entry in expected.get(&height).into_iter().flatten() {
+                let (expected, found) = counts.get_mut(entry.as_str()).unwrap();
+                if *found < *expected {
+                    *expected -= 1;
+                    report
+                        .missing
+                        .entry(height)
+                        .or_default()
+                        .push(entry.clone());
+                }
+            }
+            for entry in found.get(&height).into_iter().flatten() {
+                let (expected, found) = counts.get_mut(entry.as_str()).unwrap();
+                if *found > *expected {
+                    *found -= 1;
+                    report
+                        .duplicated
+                        .entry(height)
+                        .or_default()
+                        .push(entry.clone());
+                }
+            }
+        }
+        report
+    }
+
+    /// Leaves the blocks in `trimmed` out of the report
+    fn trim(mut self, trimmed: BTreeSet<BlockHeight>) -> Self {
+        self.missing.retain(|height, _| !trimmed.contains(height));
+        self.duplicated
+            .retain(|height, _| !trimmed.contains(height));
+        self.trimmed = trimmed;
+        self
+    }
+
+    /// Whether the compared blocks are complete, trimmed blocks aren't checked
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.duplicated.is_empty()
+    }
+}
+
+/// Serializes events the way [`PushToRedisStream`](crate::redis_handler::PushToRedisStream) does
+struct CollectingHandler {
+    transaction_format: EventFormat,
+    log_format: EventFormat,
+    transactions: BlockEntries,
+    logs: BlockEntries,
+}
+
+#[async_trait]
+impl AuroraEventHandler for CollectingHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let height = event.block_height;
+        let entry = transaction_entry(self.transaction_format, event, details)?;
+        self.transactions.entry(height).or_default().push(entry);
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let entry = log_entry(self.log_format, &event)?;
+        self.logs.entry(event.block_height).or_default().push(entry);
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, _block_height: BlockHeight) -> Result<(), HandlerError> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct EntryHeight {
+    block_height: BlockHeight,
+}
+
+/// Finds blocks whose events are missing from or duplicated in the Redis
+/// streams, by indexing the blocks again and comparing the events with the
+/// stream entries.
+///
+/// Entries are matched by block height in the event rather than by entry ID,
+/// and the [`repair_stream`] of each stream is read too, so events added by
+/// [`StreamVerifier::repair`] are found. The formats have to be the ones the
+/// streams were written with.
+pub struct StreamVerifier {
+    connection: ConnectionManager,
+    transaction_format: EventFormat,
+    log_format: EventFormat,
+    stream_prefix: String,
+    max_stream_size: usize,
+}
+
+impl StreamVerifier {
+    pub fn new(connection: ConnectionManager, max_stream_size: usize) -> Self {
+        Self {
+            connection,
+            transaction_format: EventFormat::Legacy,
+            log_format: EventFormat::Legacy,
+            stream_prefix: String::new(),
+            max_stream_size,
+        }
+    }
+
+    pub fn with_transaction_format(mut self, format: EventFormat) -> Self {
+        self.transaction_format = format;
+        self
+    }
+
+    pub fn with_log_format(mut self, format: EventFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    /// Prefix of the stream names, e.g. `script:` for the `script` sink's streams
+    pub fn with_stream_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.stream_prefix = prefix.into();
+        self
+    }
+
+    /// Reports for the transaction and log streams
+    pub async fn verify(
+        &mut self,
+        start_inclusive: BlockHeight,
+        end_exclusive: BlockHeight,
+    ) -> Result<Vec<StreamReport>, String> {
+        let mut indexer = AuroraIndexer::new(CollectingHandler {
+            transaction_format: self.transaction_format,
+            log_format: self.log_format,
+            transactions: BTreeMap::new(),
+            logs: BTreeMap::new(),
+        });
+        run_indexer(
+            &mut indexer,
+            NeardataProvider::mainnet(),
+            IndexerOptions {
+                preprocess_transactions: Some(PreprocessTransactionsSettings {
+                    prefetch_blocks: 100,
+                    postfetch_blocks: 0,
+                }),
+                ..IndexerOptions::default_with_range(BlockRange::Range {
+                    start_inclusive,
+                    end_exclusive: Some(end_exclusive),
+                })
+            },
+        )
+        .await
+        .map_err(|err| {
+            format!("Failed to index blocks {start_inclusive}..{end_exclusive}: {err:?}")
+        })?;
+
+        let mut reports = Vec::new();
+        for (stream, expected) in [
+            (AuroraTransactionEvent::ID, &indexer.handler.transactions),
+            (AuroraLogEvent::ID, &indexer.handler.logs),
+        ] {
+            let stream = format!("{}{stream}", self.stream_prefix);
+            let stream = stream.as_str();
+            let mut found = BlockEntries::new();
+            // The live writer's entry IDs are `<block height>-<index>`
+            let range = (
+                format!("{start_inclusive}-0"),
+                (end_exclusive - 1).to_string(),
+            );
+            self.read(stream, range, start_inclusive..end_exclusive, &mut found)
+                .await
+                .map_err(|err| format!("Failed to read stream {stream}: {err}"))?;
+            let repaired = repair_stream(stream);
+            self.read(
+                &repaired,
+                ("-".to_owned(), "+".to_owned()),
+                start_inclusive..end_exclusive,
+                &mut found,
+            )
+            .await
+            .map_err(|err| format!("Failed to read stream {repaired}: {err}"))?;
+            let trimmed = self
+                .trimmed_blocks(stream, expected, &found)
+                .await
+                .map_err(|err| format!("Failed to read stream {stream}: {err}"))?;
+            reports.push(StreamReport::compare(stream, expected, &found).trim(trimmed));
+        }
+        Ok(reports)
+    }
+
+    /// Blocks of `expected` or `found` that are before the first entry of
+    /// `stream`, or only partly in it because the entry isn't the first of its
+    /// block
+    async fn trimmed_blocks(
+        &mut self,
+        stream: &str,
+        expected: &BlockEntries,
+        found: &BlockEntries,
+    ) -> redis::RedisResult<BTreeSet<BlockHeight>> {
+        // The first entry, like `XINFO STREAM` has it, but without failing
+        // when the stream doesn't exist
+        let first: StreamRangeReply = self.connection.xrange_count(stream, "-", "+", 1).await?;
+        let Some(first) = first.ids.first() else {
+            return Ok(BTreeSet::new());
+        };
+        let Some((Ok(height), Ok(index))) = first
+            .id
+            .split_once('-')
+            .map(|(height, index)| (height.parse::<BlockHeight>(), index.parse::<u64>()))
+        else {
+            return Ok(BTreeSet::new());
+        };
+        Ok(expected
+            .keys()
+            .chain(found.keys())
+            .copied()
+            .filter(|block| *block < height || (*block == height && index > 0))
+            .collect())
+    }
+
+    /// Adds the entries of `stream` with IDs in `ids` and events of blocks in
+    /// `blocks` to `entries`
+    async fn read(
+        &mut self,
+        stream: &str,
+        (mut start, end): (String, String),
+        blocks: std::ops::Range<BlockHeight>,
+        entries: &mut BlockEntries,
+    ) -> redis::RedisResult<()> {
+        loop {
+            let page: StreamRangeReply = self
+                .connection
+                .xrange_count(stream, &start, &end, PAGE_SIZE)
+                .await?;
+            for entry in &page.ids {
+                let Some(event) = entry.get::<String>("event") else {
+                    continue;
+                };
+                let Ok(EntryHeight { block_height }) = serde_json::from_str(&event) else {
+                    tracing::warn!("Entry {} of {stream} has no block height", entry.id);
+                    continue;
+                };
+                if blocks.contains(&block_height) {
+                    entries.entry(block_height).or_default().push(event);
+                }
+            }
+            match page.ids.last() {
+                Some(last) if page.ids.len() == PAGE_SIZE => start = format!("({}", last.id),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Adds the missing events to the [`repair_stream`] of each stream,
+    /// returns how many were added.
+    ///
+    /// The live writer owns the IDs of the streams themselves, so repaired
+    /// events go to a separate stream, in block order with IDs picked by Redis.
+    /// Consumers that want them have to read that stream too, the events keep
+    /// their `block_height`. Events that are in the repair stream already,
+    /// e.g. when the same reports are repaired twice, aren't added again.
+    /// Trimmed blocks aren't in the reports, so they aren't repaired.
+    pub async fn repair(&mut self, reports: &[StreamReport]) -> Result<usize, String> {
+        let mut repaired = 0;
+        for report in reports {
+            let (Some(first), Some(last)) =
+                (report.missing.keys().next(), report.missing.keys().last())
+            else {
+                continue;
+            };
+            let stream = repair_stream(&report.stream);
+            let mut added = BlockEntries::new();
+            self.read(
+                &stream,
+                ("-".to_owned(), "+".to_owned()),
+                *first..*last + 1,
+                &mut added,
+            )
+            .await
+            .map_err(|err| format!("Failed to read stream {stream}: {err}"))?;
+            let missing = StreamReport::compare(&stream, &report.missing, &added).missing;
+            if missing.is_empty() {
+                continue;
+            }
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for event in missing.values().flatten() {
+                pipe.xadd_maxlen(
+                    &stream,
+                    StreamMaxlen::Approx(self.max_stream_size),
+                    "*",
+                    &[("event", event)],
+                )
+                .ignore();
+                repaired += 1;
+            }
+            pipe.query_async::<_, ()>(&mut self.connection)
+                .await
+                .map_err(|err| format!("Failed to repair stream {stream}: {err}"))?;
+        }
+        Ok(repaired)
+    }
+}
@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::HandlerError;
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the event and its spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!(
+                "Unknown log format {other}, expected `text` or `json`"
+            )),
+        }
+    }
+}
+
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// `RUST_LOG`-style directives, e.g. `info,aurora_indexer=debug`
+    pub filter: String,
+    /// Exports spans to the OTLP/HTTP collector set in the standard
+    /// `OTEL_EXPORTER_OTLP_*` variables, `http://localhost:4318` by default
+    #[cfg(feature = "otlp")]
+    pub otlp: bool,
+}
+
+impl TelemetryConfig {
+    /// `LOG_FORMAT`, `RUST_LOG` and, with the `otlp` feature,
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT`
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            log_format: match std::env::var("LOG_FORMAT") {
+                Ok(format) => format.parse()?,
+                Err(_) => LogFormat::default(),
+            },
+            filter: std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| "info,inindexer::performance=debug".to_owned()),
+            #[cfg(feature = "otlp")]
+            otlp: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok(),
+        })
+    }
+}
+
+/// Installed subscriber, [`Telemetry::shutdown`] exports the remaining spans
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider {
+            if let Err(err) = tracer_provider.shutdown() {
+                eprintln!("Failed to export remaining spans: {err}");
+            }
+        }
+    }
+}
+
+/// Sets the global subscriber, which also receives `log` records of
+/// dependencies
+pub fn init(config: TelemetryConfig) -> Result<Telemetry, HandlerError> {
+    let output = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::try_new(&config.filter)?)
+        .with(output);
+
+    #[cfg(feature = "otlp")]
+    if config.otlp {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_sdk::trace::SdkTracerProvider;
+        use opentelemetry_sdk::Resource;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()?;
+        let service_name =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "aurora-indexer".to_owned());
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build();
+        let tracer = tracer_provider.tracer("aurora-indexer");
+        subscriber
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .try_init()?;
+        return Ok(Telemetry {
+            tracer_provider: Some(tracer_provider),
+        });
+    }
+
+    subscriber.try_init()?;
+    Ok(Telemetry {
+        #[cfg(feature = "otlp")]
+        tracer_provider: None,
+    })
+}
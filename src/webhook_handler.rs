@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use aurora_engine_types::types::Address;
+use hmac::{Hmac, Mac};
+use inindexer::near_indexer_primitives::types::BlockHeight;
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::format::{EthereumLogEvent, EthereumTransactionEvent};
+use crate::{AuroraEventHandler, AuroraLogEvent, HandlerError, TransactionDetails};
+
+/// Header with the hex HMAC-SHA256 of the request body, prefixed with `sha256=`
+pub const SIGNATURE_HEADER: &str = "X-Aurora-Signature";
+/// Header with the delivery id, which stays the same across retries
+pub const DELIVERY_HEADER: &str = "X-Aurora-Delivery";
+
+/// Which events a subscriber receives. Empty lists match anything.
+///
+/// Transactions are matched by `from`, `to` and `selectors`, and are only
+/// delivered when no `topics` are set. Logs are matched by the `from` and
+/// `selectors` of their transaction, the emitting contract (`to`) and
+/// `topics`, where any of the log's topics can match.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct WebhookFilter {
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub from: Vec<[u8; 20]>,
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub to: Vec<[u8; 20]>,
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub selectors: Vec<[u8; 4]>,
+    #[serde(default, deserialize_with = "crate::format::hex_list")]
+    pub topics: Vec<[u8; 32]>,
+}
+
+impl WebhookFilter {
+    pub fn matches_transaction(&self, event: &AuroraTransactionEvent) -> bool {
+        self.topics.is_empty()
+            && self.matches_call(&event.from, &event.input)
+            && (self.to.is_empty() || event.to.is_some_and(|to| self.to.contains(&to.raw().0)))
+    }
+
+    /// `from` and `input` are the ones of the log's transaction
+    pub fn matches_log(&self, from: &Address, input: &[u8], event: &AuroraLogEvent) -> bool {
+        self.matches_call(from, input)
+            && (self.to.is_empty() || self.to.contains(&event.address.raw().0))
+            && (self.topics.is_empty()
+                || event.topics.iter().any(|topic| self.topics.contains(topic)))
+    }
+
+    fn matches_call(&self, from: &Address, input: &[u8]) -> bool {
+        (self.from.is_empty() || self.from.contains(&from.raw().0))
+            && (self.selectors.is_empty()
+                || input
+                    .get(..4)
+                    .is_some_and(|selector| self.selectors.iter().any(|s| s == selector)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Subscriber {
+    pub url: String,
+    /// Key of the HMAC signature
+    pub secret: String,
+    #[serde(default)]
+    pub filter: WebhookFilter,
+}
+
+pub struct WebhookConfig {
+    pub subscribers: Vec<Subscriber>,
+    /// File deliveries are kept in until they succeed
+    pub queue_path: PathBuf,
+    /// Deliveries that are sent at the same time
+    pub concurrency: usize,
+    /// Delivery is dropped after this many failed attempts
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every attempt ...
+    pub initial_backoff: Duration,
+    /// ... up to this
+    pub max_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(subscribers: Vec<Subscriber>, queue_path: impl Into<PathBuf>) -> Self {
+        Self {
+            subscribers,
+            queue_path: queue_path.into(),
+            concurrency: 16,
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60 * 60),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Delivery {
+    id: String,
+    url: String,
+    body: String,
+    attempts: u32,
+    /// Unix timestamp in milliseconds
+    next_attempt: u64,
+}
+
+/// Deliveries that haven't succeeded yet, shared with the delivery task
+struct Queue {
+    deliveries: Mutex<Vec<Delivery>>,
+    /// Wakes the delivery task up when deliveries are added or the handler stops
+    wake: Notify,
+    /// The delivery task stops once no deliveries are due
+    stopping: AtomicBool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "event", rename_all = "snake_case")]
+enum Payload {
+    Transaction(EthereumTransactionEvent),
+    Log(EthereumLogEvent),
+}
+
+/// POSTs events matching a subscriber's filter to its URL.
+///
+/// Bodies are `{"type": "transaction" | "log", "event": ...}` in the
+/// Ethereum-style format, signed with the subscriber's secret. Flushing a
+/// block only adds its deliveries to the queue and persists it, a background
+/// task sends them, at most [`WebhookConfig::concurrency`] at a time, and
+/// retries failed ones with exponential backoff. Deliveries stay in the
+/// persisted queue until they succeed, so that a restart doesn't lose them.
+///
+/// Must be created inside a Tokio runtime.
+pub struct WebhookHandler {
+    config: Arc<WebhookConfig>,
+    pending: Vec<Delivery>,
+    queue: Arc<Queue>,
+    delivery_task: Option<JoinHandle<()>>,
+    /// Sender and selector of the last transaction, its logs are matched by them
+    sender: Address,
+    selector: Vec<u8>,
+}
+
+impl WebhookHandler {
+    pub fn new(config: WebhookConfig) -> std::io::Result<Self> {
+        let deliveries = match std::fs::read(&config.queue_path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("Failed to create HTTP client");
+        let config = Arc::new(config);
+        let queue = Arc::new(Queue {
+            deliveries: Mutex::new(deliveries),
+            wake: Notify::new(),
+            stopping: AtomicBool::new(false),
+        });
+        let delivery_task = tokio::spawn(deliver_queue(config.clone(), client, queue.clone()));
+        Ok(Self {
+            config,
+            pending: Vec::new(),
+            sender: Address::zero(),
+            selector: Vec::new(),
+            queue,
+            delivery_task: Some(delivery_task),
+        })
+    }
+
+    fn push(&mut self, id: String, payload: Payload, subscribers: Vec<String>) {
+        if subscribers.is_empty() {
+            return;
+        }
+        let body = serde_json::to_string(&payload).expect("Failed to serialize webhook payload");
+        for url in subscribers {
+            self.pending.push(Delivery {
+                id: id.clone(),
+                url,
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: 0,
+            });
+        }
+    }
+
+    fn stop(&self) {
+        self.queue.stopping.store(true, Ordering::Release);
+        self.queue.wake.notify_one();
+    }
+}
+
+impl Drop for WebhookHandler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    delivery: &Delivery,
+) -> Result<(), String> {
+    let Some(subscriber) = config
+        .subscribers
+        .iter()
+        .find(|subscriber| subscriber.url == delivery.url)
+    else {
+        tracing::warn!(
+            "Dropping webhook delivery {} to {}, no longer subscribed",
+            delivery.id,
+            delivery.url
+        );
+        return Ok(());
+    };
+    client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, sign(&subscriber.secret, &delivery.body))
+        .header(DELIVERY_HEADER, &delivery.id)
+        .body(delivery.body.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Sends the due deliveries of the queue until the handler stops and none are
+/// due. Deliveries are only removed from the queue once they succeed or are
+/// given up on.
+async fn deliver_queue(config: Arc<WebhookConfig>, client: reqwest::Client, queue: Arc<Queue>) {
+    loop {
+        let now = now_millis();
+        let (due, next_attempt) = {
+            let deliveries = queue.deliveries.lock().unwrap();
+            let due = deliveries
+                .iter()
+                .filter(|delivery| delivery.next_attempt <= now)
+                .cloned()
+                .collect::<Vec<_>>();
+            let next_attempt = deliveries
+                .iter()
+                .map(|delivery| delivery.next_attempt)
+                .filter(|&next_attempt| next_attempt > now)
+                .min();
+            (due, next_attempt)
+        };
+        if due.is_empty() {
+            if queue.stopping.load(Ordering::Acquire) {
+                return;
+            }
+            let delay = next_attempt.map_or(Duration::MAX, |next_attempt| {
+                Duration::from_millis(next_attempt - now)
+            });
+            tokio::select! {
+                _ = queue.wake.notified() => {}
+                _ = tokio::time::sleep(delay) => {}
+            }
+            continue;
+        }
+
+        let mut results = Vec::with_capacity(due.len());
+        let mut tasks = JoinSet::new();
+        for delivery in due {
+            if tasks.len() >= config.concurrency.max(1) {
+                if let Some(result) = tasks.join_next().await {
+                    results.push(result.expect("Webhook delivery panicked"));
+                }
+            }
+            let client = client.clone();
+            let config = config.clone();
+            tasks.spawn(async move {
+                let result = deliver(&client, &config, &delivery).await;
+                (delivery, result)
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.expect("Webhook delivery panicked"));
+        }
+
+        let now = now_millis();
+        let mut deliveries = queue.deliveries.lock().unwrap();
+        for (delivery, result) in results {
+            let Some(position) = deliveries
+                .iter()
+                .position(|queued| queued.id == delivery.id && queued.url == delivery.url)
+            else {
+                continue;
+            };
+            let Err(err) = result else {
+                deliveries.remove(position);
+                continue;
+            };
+            let queued = &mut deliveries[position];
+            queued.attempts += 1;
+            if queued.attempts >= config.max_attempts {
+                tracing::error!(
+                    "Giving up on webhook delivery {} to {} after {} attempts: {err}",
+                    queued.id,
+                    queued.url,
+                    queued.attempts
+                );
+                deliveries.remove(position);
+                continue;
+            }
+            let backoff = config
+                .initial_backoff
+                .saturating_mul(2u32.saturating_pow(queued.attempts - 1))
+                .min(config.max_backoff);
+            tracing::warn!(
+                "Webhook delivery {} to {} failed, retrying in {backoff:?}: {err}",
+                queued.id,
+                queued.url
+            );
+            queued.next_attempt = now + backoff.as_millis() as u64;
+        }
+        if let Err(err) = save_queue(&config.queue_path, &deliveries) {
+            tracing::error!("Failed to save the webhook queue: {err}");
+        }
+    }
+}
+
+fn save_queue(path: &Path, deliveries: &[Delivery]) -> std::io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_vec(deliveries)?)?;
+    std::fs::rename(temporary, path)
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `body`
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+#[async_trait]
+impl AuroraEventHandler for WebhookHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        let subscribers = self
+            .config
+            .subscribers
+            .iter()
+            .filter(|subscriber| subscriber.filter.matches_transaction(&event))
+            .map(|subscriber| subscriber.url.clone())
+            .collect();
+        self.sender = event.from;
+        self.selector = event.input.iter().take(4).copied().collect();
+        let id = format!("{}:{:#x}", event.receipt_id, details.tx_hash);
+        let payload = Payload::Transaction(EthereumTransactionEvent::new(&event, &details));
+        self.push(id, payload, subscribers);
+        Ok(())
+    }
+
+    async fn handle_log(&mut self, event: AuroraLogEvent) -> Result<(), HandlerError> {
+        let subscribers = self
+            .config
+            .subscribers
+            .iter()
+            .filter(|subscriber| {
+                subscriber
+                    .filter
+                    .matches_log(&self.sender, &self.selector, &event)
+            })
+            .map(|subscriber| subscriber.url.clone())
+            .collect();
+        let id = format!(
+            "{}:{}:{}",
+            event.receipt_id, event.aurora_tx_hash, event.log_index
+        );
+        self.push(id, Payload::Log(EthereumLogEvent::new(&event)), subscribers);
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, _block_height: BlockHeight) -> Result<(), HandlerError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut deliveries = self.queue.deliveries.lock().unwrap();
+            let queued = deliveries.len();
+            deliveries.extend(self.pending.iter().cloned());
+            if let Err(err) = save_queue(&self.config.queue_path, &deliveries) {
+                // Kept in `pending` for the next attempt
+                deliveries.truncate(queued);
+                return Err(err.into());
+            }
+        }
+        self.pending.clear();
+        self.queue.wake.notify_one();
+        Ok(())
+    }
+
+    async fn discard_events(&mut self) -> Result<(), HandlerError> {
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Waits until the deliveries that are due are sent, the ones waiting for
+    /// a retry stay in the queue file
+    async fn finish(&mut self) -> Result<(), HandlerError> {
+        self.stop();
+        if let Some(delivery_task) = self.delivery_task.take() {
+            delivery_task.await?;
+        }
+        Ok(())
+    }
+}
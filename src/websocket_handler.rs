@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::routing::get;
+use axum::Router;
+use inindexer::near_indexer_primitives::types::{AccountId, BlockHeight};
+use intear_events::events::aurora::transaction::AuroraTransactionEvent;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::format::{hex_bytes, quantity, EthereumReceiptLog, EthereumTransactionEvent};
+use crate::{
+    aurora_block_hash, AuroraBlockEvent, AuroraEventHandler, HandlerError, TransactionDetails,
+};
+
+/// Blocks buffered for each client, one that falls further behind is
+/// disconnected
+pub const DEFAULT_BUFFER_BLOCKS: usize = 256;
+/// Most subscriptions a connection can have at once
+pub const MAX_SUBSCRIPTIONS: usize = 100;
+/// A client that doesn't take a message within this time is disconnected
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Close code for clients that can't keep up, "Try Again Later"
+const CLOSE_TOO_SLOW: u16 = 1013;
+
+/// Events of one Aurora block, as sent to subscribers
+#[derive(Default)]
+struct BlockUpdate {
+    /// `newHeads` result, `None` if the block event wasn't seen
+    head: Option<Value>,
+    logs: Vec<EthereumReceiptLog>,
+    /// `auroraTransactions` results
+    transactions: Vec<Value>,
+}
+
+/// Clients of the WebSocket server, which [`WebSocketHandler`] publishes
+/// blocks to. Clones share the same clients, so one instance can be passed to
+/// the handler and served with [`router`].
+#[derive(Clone)]
+pub struct Subscriptions {
+    sender: broadcast::Sender<Arc<BlockUpdate>>,
+}
+
+impl Subscriptions {
+    /// `buffer_blocks` blocks are kept for each client, see
+    /// [`DEFAULT_BUFFER_BLOCKS`]
+    pub fn new(buffer_blocks: usize) -> Self {
+        Self {
+            sender: broadcast::channel(buffer_blocks).0,
+        }
+    }
+
+    /// Connected clients with at least one subscription
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_BLOCKS)
+    }
+}
+
+/// Publishes the blocks of one engine account to WebSocket clients.
+///
+/// Events are collected and published when the block is flushed, so clients
+/// only see complete blocks. Publishing never waits for clients: each one has
+/// its own buffer of blocks, and is disconnected when it's full.
+pub struct WebSocketHandler {
+    subscriptions: Subscriptions,
+    engine_account_id: AccountId,
+    block: BlockUpdate,
+}
+
+impl WebSocketHandler {
+    pub fn new(subscriptions: Subscriptions) -> Self {
+        Self {
+            subscriptions,
+            engine_account_id: "aurora".parse().unwrap(),
+            block: BlockUpdate::default(),
+        }
+    }
+
+    /// Engine account whose blocks are published, `aurora` by default
+    pub fn with_engine_account_id(mut self, engine_account_id: AccountId) -> Self {
+        self.engine_account_id = engine_account_id;
+        self
+    }
+}
+
+#[async_trait]
+impl AuroraEventHandler for WebSocketHandler {
+    async fn handle_transaction(
+        &mut self,
+        event: AuroraTransactionEvent,
+        details: TransactionDetails,
+    ) -> Result<(), HandlerError> {
+        if details.engine_account_id != self.engine_account_id {
+            return Ok(());
+        }
+        self.block.logs.extend(details.receipt.logs.iter().cloned());
+        self.block
+            .transactions
+            .push(serde_json::to_value(EthereumTransactionEvent::new(
+                &event, &details,
+            ))?);
+        Ok(())
+    }
+
+    async fn handle_block(&mut self, event: AuroraBlockEvent) -> Result<(), HandlerError> {
+        if event.engine_account_id == self.engine_account_id {
+            self.block.head = Some(head(&event));
+        }
+        Ok(())
+    }
+
+    async fn flush_events(&mut self, _block_height: BlockHeight) -> Result<(), HandlerError> {
+        let block = std::mem::take(&mut self.block);
+        // Fails only when nobody is subscribed
+        let _ = self.subscriptions.sender.send(Arc::new(block));
+        Ok(())
+    }
+}
+
+/// `newHeads` result, limited to what the indexer knows about the block
+fn head(event: &AuroraBlockEvent) -> Value {
+    let parent_hash = match (event.chain_id, event.block_height.checked_sub(1)) {
+        (Some(chain_id), Some(parent_height)) => Some(format!(
+            "{:#x}",
+            aurora_block_hash(chain_id, &event.engine_account_id, parent_height)
+        )),
+        _ => None,
+    };
+    json!({
+        "number": quantity(event.block_height),
+        "hash": event.block_hash.map(|hash| format!("{hash:#x}")),
+        "parentHash": parent_hash,
+        "timestamp": quantity((event.block_timestamp_nanosec / 1_000_000_000) as u64),
+        "gasUsed": quantity(event.gas_used),
+        "logsBloom": event.logs_bloom.to_hex(),
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    /// Lowercase `0x` hex of each value, which must have `len` bytes
+    fn normalize(self, len: usize) -> Result<Vec<String>, String> {
+        let values = match self {
+            Self::One(value) => vec![value],
+            Self::Many(values) => values,
+        };
+        values
+            .into_iter()
+            .map(|value| {
+                hex::decode(value.trim_start_matches("0x"))
+                    .ok()
+                    .filter(|bytes| bytes.len() == len)
+                    .map(|bytes| hex_bytes(&bytes))
+                    .ok_or_else(|| format!("Expected {len} hex bytes, got {value}"))
+            })
+            .collect()
+    }
+}
+
+/// `logs` subscription filter, like the one of `eth_getLogs` without the block
+/// range
+#[derive(Deserialize, Default)]
+struct LogFilterParams {
+    address: Option<OneOrMany>,
+    #[serde(default)]
+    topics: Vec<Option<OneOrMany>>,
+}
+
+struct LogFilter {
+    /// Any of these addresses, or any address if empty
+    addresses: Vec<String>,
+    /// Any of the topics at each position, `None` matches anything
+    topics: Vec<Option<Vec<String>>>,
+}
+
+impl LogFilter {
+    fn new(params: LogFilterParams) -> Result<Self, String> {
+        Ok(Self {
+            addresses: params
+                .address
+                .map(|address| address.normalize(20))
+                .transpose()?
+                .unwrap_or_default(),
+            topics: params
+                .topics
+                .into_iter()
+                .map(|topics| topics.map(|topics| topics.normalize(32)).transpose())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn matches(&self, log: &EthereumReceiptLog) -> bool {
+        (self.addresses.is_empty() || self.addresses.contains(&log.address.to_lowercase()))
+            && self
+                .topics
+                .iter()
+                .enumerate()
+                .all(|(i, topics)| match topics {
+                    None => true,
+                    Some(topics) => log
+                        .topics
+                        .get(i)
+                        .is_some_and(|topic| topics.contains(topic)),
+                })
+    }
+}
+
+enum Subscription {
+    NewHeads,
+    Logs(LogFilter),
+    AuroraTransactions,
+}
+
+impl Subscription {
+    fn results<'a>(&'a self, block: &'a BlockUpdate) -> Box<dyn Iterator<Item = Value> + 'a> {
+        match self {
+            Self::NewHeads => Box::new(block.head.iter().cloned()),
+            Self::Logs(filter) => Box::new(
+                block
+                    .logs
+                    .iter()
+                    .filter(|log| filter.matches(log))
+                    .map(|log| serde_json::to_value(log).unwrap()),
+            ),
+            Self::AuroraTransactions => Box::new(block.transactions.iter().cloned()),
+        }
+    }
+}
+
+struct RequestError {
+    code: i64,
+    message: String,
+}
+
+/// State of one WebSocket connection
+struct Connection {
+    subscriptions: Subscriptions,
+    /// Receives blocks while there's at least one subscription
+    updates: Option<broadcast::Receiver<Arc<BlockUpdate>>>,
+    active: HashMap<String, Subscription>,
+    next_id: u64,
+}
+
+impl Connection {
+    fn handle(&mut self, text: &str) -> Value {
+        match serde_json::from_str::<Value>(text) {
+            Ok(Value::Array(requests)) if !requests.is_empty() => Value::Array(
+                requests
+                    .into_iter()
+                    .map(|request| self.handle_request(request))
+                    .collect(),
+            ),
+            Ok(request) => self.handle_request(request),
+            Err(err) => response(
+                Value::Null,
+                Err(RequestError {
+                    code: -32700,
+                    message: format!("Parse error: {err}"),
+                }),
+            ),
+        }
+    }
+
+    fn handle_request(&mut self, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return response(
+                id,
+                Err(RequestError {
+                    code: -32600,
+                    message: "Missing method".to_owned(),
+                }),
+            );
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let result = match method {
+            "eth_subscribe" => self.subscribe(&params),
+            "eth_unsubscribe" => Ok(self.unsubscribe(&params)),
+            other => Err(RequestError {
+                code: -32601,
+                message: format!(
+                    "Method {other} not found, only eth_subscribe and eth_unsubscribe are supported"
+                ),
+            }),
+        };
+        response(id, result)
+    }
+
+    fn subscribe(&mut self, params: &Value) -> Result<Value, RequestError> {
+        let invalid_params = |message: String| RequestError {
+            code: -32602,
+            message,
+        };
+        let subscription = match params.get(0).and_then(Value::as_str) {
+            Some("newHeads") => Subscription::NewHeads,
+            Some("logs") => {
+                let filter = match params.get(1) {
+                    Some(filter) => serde_json::from_value(filter.clone())
+                        .map_err(|err| invalid_params(format!("Invalid log filter: {err}")))?,
+                    None => LogFilterParams::default(),
+                };
+                Subscription::Logs(LogFilter::new(filter).map_err(invalid_params)?)
+            }
+            Some("auroraTransactions") => Subscription::AuroraTransactions,
+            other => {
+                return Err(invalid_params(format!(
+                    "Unknown subscription {other:?}, expected newHeads, logs or auroraTransactions"
+                )))
+            }
+        };
+        if self.active.len() >= MAX_SUBSCRIPTIONS {
+            return Err(RequestError {
+                code: -32005,
+                message: format!("At most {MAX_SUBSCRIPTIONS} subscriptions per connection"),
+            });
+        }
+        let id = quantity(self.next_id);
+        self.next_id += 1;
+        self.active.insert(id.clone(), subscription);
+        if self.updates.is_none() {
+            self.updates = Some(self.subscriptions.sender.subscribe());
+        }
+        Ok(Value::String(id))
+    }
+
+    fn unsubscribe(&mut self, params: &Value) -> Value {
+        let removed = params
+            .get(0)
+            .and_then(Value::as_str)
+            .is_some_and(|id| self.active.remove(id).is_some());
+        if self.active.is_empty() {
+            // Nothing would read the buffer, so stop filling it
+            self.updates = None;
+        }
+        Value::Bool(removed)
+    }
+
+    /// `eth_subscription` notifications for `block`
+    fn notifications(&self, block: &BlockUpdate) -> Vec<String> {
+        self.active
+            .iter()
+            .flat_map(|(id, subscription)| {
+                subscription.results(block).map(move |result| {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": { "subscription": id, "result": result },
+                    })
+                    .to_string()
+                })
+            })
+            .collect()
+    }
+}
+
+fn response(id: Value, result: Result<Value, RequestError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(RequestError { code, message }) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+/// Whether `text` was sent, the connection should be dropped otherwise
+async fn send(socket: &mut WebSocket, text: String) -> bool {
+    match tokio::time::timeout(SEND_TIMEOUT, socket.send(Message::Text(text))).await {
+        Ok(result) => result.is_ok(),
+        Err(_) => {
+            tracing::info!("Disconnecting WebSocket client that stopped reading");
+            false
+        }
+    }
+}
+
+async fn serve(mut socket: WebSocket, subscriptions: Subscriptions) {
+    let mut connection = Connection {
+        subscriptions,
+        updates: None,
+        active: HashMap::new(),
+        next_id: 1,
+    };
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                };
+                let response = connection.handle(&text);
+                if !send(&mut socket, response.to_string()).await {
+                    return;
+                }
+            }
+            update = async { connection.updates.as_mut().unwrap().recv().await },
+                if connection.updates.is_some() =>
+            {
+                let block = match update {
+                    Ok(block) => block,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::info!("Disconnecting WebSocket client {skipped} blocks behind");
+                        let close = Message::Close(Some(CloseFrame {
+                            code: CLOSE_TOO_SLOW,
+                            reason: format!("Client is {skipped} blocks behind").into(),
+                        }));
+                        let _ = tokio::time::timeout(SEND_TIMEOUT, socket.send(close)).await;
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                for notification in connection.notifications(&block) {
+                    if !send(&mut socket, notification).await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `GET /`, upgraded to a WebSocket that answers `eth_subscribe` for
+/// `newHeads`, `logs` and `auroraTransactions`, and `eth_unsubscribe`
+pub fn router(subscriptions: Subscriptions) -> Router {
+    Router::new().route(
+        "/",
+        get(move |upgrade: WebSocketUpgrade| async move {
+            upgrade.on_upgrade(move |socket| serve(socket, subscriptions))
+        }),
+    )
+}